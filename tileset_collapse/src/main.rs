@@ -1,15 +1,26 @@
-use image::{ImageBuffer, GenericImageView, DynamicImage, ImageError};
+use image::{ImageError, GenericImageView, Rgba, RgbaImage};
 use std::collections::HashMap;
-use rand::Rng;
-use glob;
-use rand;
-use std::fmt;
-use ggez::{ContextBuilder, event};
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
 use std::env;
+use std::fs::File;
 use std::path::Path;
 use pbr::ProgressBar;
 use std::process;
 
+mod preview;
+mod tiled;
+// Shared with wave_collapse rather than copied
+#[path = "../../wave_collapse/src/font.rs"]
+mod font;
+#[path = "../../wave_collapse/src/paths.rs"]
+mod paths;
+#[path = "../../wave_collapse/src/tiled_map.rs"]
+mod tiled_map;
+
+// Neighbour offsets by direction: up, down, left, right
+const DIRECTIONS: [(i32, i32); 4] = [(-1, 0), (1, 0), (0, -1), (0, 1)];
+
 fn load_image_to_bitmap(image_path: &str) -> Vec<Vec<[i32; 3]>> {
     let img = image::open(image_path).unwrap();
     let (width, height) = img.dimensions();
@@ -29,112 +40,95 @@ fn load_image_to_bitmap(image_path: &str) -> Vec<Vec<[i32; 3]>> {
     bitmap
 }
 
-fn get_ruleset() -> HashMap<usize, Vec<usize>> {
-    let mut rules = HashMap::new();
-    rules.insert(1, vec![1,2,3]);
-    rules.insert(2, vec![1,2,3]);
-    rules.insert(3, vec![1,2,3,4]);
-    rules.insert(4, vec![1,3,4,5]);
-    rules.insert(5, vec![4,5,6]);
-    rules.insert(6, vec![5,6]);
-    rules
+// Pixels packed as 0xRRGGBB, so the transforms can move them around like any bitmap
+fn load_packed_bitmap(path: &Path) -> Vec<Vec<i32>> {
+    load_image_to_bitmap(&path.to_string_lossy()).into_iter()
+        .map(|row| row.into_iter().map(|[r, g, b]| (r << 16) | (g << 8) | b).collect())
+        .collect()
 }
 
-fn stitch_images(grid: &Grid) -> Result<(), ImageError> {
-    // Load the first image to get the dimensions
-    let first_tile = &grid.cells[0][0];
-    let first_tile_name = grid.id_to_name(first_tile.value.unwrap() as u32);
-
-    let first_tile_path = format!("tileset/{}_{}.png", first_tile.value.unwrap(), first_tile_name);
-
-    println!("Trying to open: {}", first_tile_path);
-
-    let first_image: DynamicImage = image::open(&Path::new(&first_tile_path))?;
-
-    // get the dimensions
-    let single_image_width = first_image.width();
-    let single_image_height = first_image.height();
+fn unpack_bitmap(bitmap: &[Vec<i32>]) -> RgbaImage {
+    let size = bitmap.len() as u32;
+    RgbaImage::from_fn(size, size, |x, y| {
+        let pixel = bitmap[y as usize][x as usize];
+        Rgba([(pixel >> 16) as u8, (pixel >> 8) as u8, pixel as u8, 255])
+    })
+}
 
-    // create an empty image with the size of all combined images
-    let image_width = single_image_width * grid.cells.len() as u32;
-    let image_height = single_image_height * grid.cells.len() as u32;
-    let mut final_image = ImageBuffer::new(image_width, image_height);
+// Draws every collapsed cell as the variant it got, the same one the Tiled export flips to
+fn stitch_images(grid: &Grid) -> Result<(), ImageError> {
+    let tile_size = grid.tile_transforms.values().flatten().map(|bitmap| bitmap.len() as u32).max().unwrap_or(0);
+    let rows = grid.cells.len() as u32;
+    let columns = grid.cells.first().map_or(0, |row| row.len()) as u32;
+    let mut final_image = RgbaImage::new(columns * tile_size, rows * tile_size);
 
     for (y, row) in grid.cells.iter().enumerate() {
         for (x, cell) in row.iter().enumerate() {
-            if let Some(value) = cell.value {
-                let tile_name = grid.id_to_name(value as u32);
-                let tile_path = format!("tileset/{}_{}.png", value, tile_name);
-                let tile_image = image::open(&Path::new(&tile_path))?.into_rgba8();
-                
+            if let Some(bitmap) = grid.cell_bitmap(cell) {
                 // paste the image at the correct position
-                let top_left_x = (x * single_image_width as usize) as u32;
-                let top_left_y = (y * single_image_height as usize) as u32;
-                image::imageops::overlay(&mut final_image, &tile_image, top_left_x, top_left_y);
+                let top_left_x = x as u32 * tile_size;
+                let top_left_y = y as u32 * tile_size;
+                image::imageops::overlay(&mut final_image, &unpack_bitmap(bitmap), top_left_x, top_left_y);
             }
         }
     }
-    
+
     println!("Saving final image");
     // save the final image
     final_image.save("final_image.png")
 }
 
-fn load_tiles(possible_values: Vec<usize>, current_dir: &Path) -> (Vec<Tile>, HashMap<String, Vec<Vec<Vec<i32>>>>) {
-    let tileset_path = current_dir.join("tileset/*.png");
-    let mut tiles = vec![];
-    let mut tile_transforms: HashMap<String, Vec<Vec<Vec<i32>>>> = HashMap::new();
-
-    for entry in glob::glob(tileset_path.to_str().unwrap()).expect("Failed to read glob pattern") {
-        match entry {
-            Ok(path) => {
-                let filename = path.file_stem().unwrap().to_string_lossy();
-                println!("Processing file: {}", filename);
-
-                let parts: Vec<_> = filename.split('_').collect();
-
-                if parts.len() != 2 {
-                    println!("Unexpected filename format: {}", filename);
-                    continue;
-                }
-
-                let id = match parts[1].parse::<usize>() {
-                    Ok(id) => id,
-                    Err(_) => {
-                        println!("Failed to parse id from filename: {}", filename);
-                        continue;
-                    }
-                };
-
-                let name = parts[1].to_string();
-                let bitmap = vec![];
-                
-                // Extract symmetry from the filename
-                let symmetry = match parts[0].chars().next().unwrap() {
-                    'L' => Symmetry::L,
-                    'T' => Symmetry::T,
-                    'I' => Symmetry::I,
-                    '\\' => Symmetry::BackSlash,
-                    '/' => Symmetry::ForwardSlash,
-                    'F' => Symmetry::F,
-                    'X' => Symmetry::X,
-                    _ => {
-                        println!("Invalid symmetry in filename: {}", filename);
-                        continue;
-                    }
-                };
-
-                let weight = 0.0;
-                tiles.push(Tile::new(id, name.clone(), bitmap, symmetry, weight, possible_values.clone()));
-                tile_transforms.insert(name.clone(), tiles.last().unwrap().generate_transforms());
+// Every `<id>.gif` in `dir` with its class from `tilesetSymmetries.json`, by id.
+// Tiles without a class are only ever used the way they are drawn.
+fn load_tiles(dir: &Path) -> Result<Vec<Tile>, String> {
+    let symmetries_path = dir.join("tilesetSymmetries.json");
+    let symmetries: HashMap<String, String> = File::open(&symmetries_path)
+        .map_err(|e| e.to_string())
+        .and_then(|file| serde_json::from_reader(file).map_err(|e| e.to_string()))
+        .map_err(|e| format!("{}: {}", symmetries_path.display(), e))?;
 
-                println!("Successfully loaded tile with id: {}, name: {}", id, name);
+    let pattern = dir.join("*.gif");
+    let mut tiles = vec![];
+    for path in glob::glob(&pattern.to_string_lossy()).map_err(|e| e.to_string())?.flatten() {
+        let name = path.file_stem().unwrap().to_string_lossy().into_owned();
+        let id = match name.parse::<usize>() {
+            Ok(id) => id,
+            Err(_) => {
+                println!("Skipping {}, its name is not a tile id", path.display());
+                continue;
             }
-            Err(e) => println!("Error encountered: {:?}", e),
+        };
+        let bitmap = load_packed_bitmap(&path);
+        // The transforms turn the bitmap around its centre, which needs a square
+        if bitmap.iter().any(|row| row.len() != bitmap.len()) {
+            println!("Skipping tile {}, it is not square", id);
+            continue;
+        }
+        let class = symmetries.get(&name).map_or("", String::as_str);
+        let symmetry = parse_symmetry(class);
+        if symmetry.is_none() {
+            println!("Tile {} has no symmetry class in {}, using it untransformed", id, symmetries_path.display());
         }
+        let weight = 0.0;
+        tiles.push(Tile::new(id, name, bitmap, symmetry, weight, vec![]));
     }
+    tiles.sort_by_key(|tile| tile.id);
     println!("Loaded {} tiles in total.", tiles.len());
-    (tiles, tile_transforms)
+    Ok(tiles)
+}
+
+// Symmetry class from its first letter, as in filenames and `tilesetSymmetries.json`
+fn parse_symmetry(class: &str) -> Option<Symmetry> {
+    match class.chars().next()? {
+        'L' => Some(Symmetry::L),
+        'T' => Some(Symmetry::T),
+        'I' => Some(Symmetry::I),
+        '\\' => Some(Symmetry::BackSlash),
+        '/' => Some(Symmetry::ForwardSlash),
+        'F' => Some(Symmetry::F),
+        'X' => Some(Symmetry::X),
+        _ => None,
+    }
 }
 
 #[derive(Clone)]
@@ -148,37 +142,97 @@ enum Symmetry {
     X
 }
 
+impl Symmetry {
+    // The letter `parse_symmetry` reads
+    fn class(&self) -> &'static str {
+        match self {
+            Symmetry::L => "L",
+            Symmetry::T => "T",
+            Symmetry::I => "I",
+            Symmetry::BackSlash => "\\",
+            Symmetry::ForwardSlash => "/",
+            Symmetry::F => "F",
+            Symmetry::X => "X",
+        }
+    }
+
+    // The variants `Tile::generate_transforms` returns for this class, in the same order
+    fn transforms(&self) -> &'static [Transform] {
+        match self {
+            Symmetry::L => &[Transform::Original, Transform::Cw1, Transform::Cw2, Transform::Cw3],
+            Symmetry::T => &[Transform::Original, Transform::Cw1, Transform::Cw2],
+            Symmetry::I => &[Transform::Original, Transform::Cw1],
+            Symmetry::BackSlash | Symmetry::ForwardSlash => &[Transform::Original, Transform::Reflect],
+            Symmetry::F => &[Transform::Original, Transform::Reflect, Transform::Cw1, Transform::Cw2, Transform::Cw3],
+            Symmetry::X => &[Transform::Original, Transform::Reflect],
+        }
+    }
+}
+
+// What `Tile::generate_transforms` did to the bitmap to get a variant; cwN is N clockwise quarter turns
+#[derive(Clone, Copy)]
+enum Transform {
+    Original,
+    Cw1,
+    Cw2,
+    Cw3,
+    Reflect,
+}
+
+impl Transform {
+    fn name(&self) -> &'static str {
+        match self {
+            Transform::Original => "original",
+            Transform::Cw1 => "cw1",
+            Transform::Cw2 => "cw2",
+            Transform::Cw3 => "cw3",
+            Transform::Reflect => "refl",
+        }
+    }
+}
+
 pub struct Grid {
-    cells: Vec<Vec<Tile>>, // A 2D grid of tiles
-    rules: HashMap<usize, Vec<usize>>,
+    cells: Vec<Vec<Tile>>, // A 2D grid of tiles, `possible_values` index into `variants`
     initial_collapse_done: bool,
     tile_transforms: HashMap<String, Vec<Vec<Vec<i32>>>>, // Use String as the key
+    symmetries: HashMap<usize, Symmetry>, // Symmetry class by tile id, to tell which variant a cell got
+    variants: Vec<(usize, usize)>, // (tile id, orientation) of every distinct variant
+    compatible: Vec<Vec<Vec<usize>>>, // Per direction and variant, the variants whose facing edge matches
+    rng: StdRng,
 }
 
 #[derive(Clone)]
 struct Tile {
     id: usize,
     name: String,
-    symmetry: Symmetry,
+    symmetry: Option<Symmetry>,
     weight: f32,
     value: Option<usize>,
+    // Index into the `Tile::transforms` of the value's tile, the variant placed here
+    orientation: usize,
     bitmap: Vec<Vec<i32>>, 
     possible_values: Vec<usize>,
 }
 
 impl Tile {
-    fn new(id: usize, name: String, bitmap: Vec<Vec<i32>>, symmetry: Symmetry, weight: f32, possible_values: Vec<usize>) -> Self {
+    fn new(id: usize, name: String, bitmap: Vec<Vec<i32>>, symmetry: Option<Symmetry>, weight: f32, possible_values: Vec<usize>) -> Self {
         Self {
             id,
             name,
             symmetry,
             weight,
             value: None,
+            orientation: 0,
             bitmap,
             possible_values,
         }
     }
 
+    // The variants `generate_transforms` returns, only the original without a symmetry class
+    fn transforms(&self) -> &'static [Transform] {
+        self.symmetry.as_ref().map_or(&[Transform::Original], Symmetry::transforms)
+    }
+
     fn rotate_cw_twice(&self) -> Vec<Vec<i32>> {
         let mut temp = self.clone();
         temp.bitmap = self.rotate_cw();
@@ -186,110 +240,138 @@ impl Tile {
     }
 
     fn rotate_cw(&self) -> Vec<Vec<i32>> {
+        // Row r of the turned bitmap is column r of the original, read bottom up
         let n = self.bitmap.len();
-        let mut new_bitmap = vec![vec![0; n]; n];
-        for i in 0..n {
-            for j in 0..n {
-                new_bitmap[j][n-i-1] = self.bitmap[i][j];
-            }
-        }
-        new_bitmap
+        (0..n).map(|r| self.bitmap.iter().rev().map(|row| row[r]).collect()).collect()
     }
     
     fn reflect(&self) -> Vec<Vec<i32>> {
-        let n = self.bitmap.len();
-        let mut new_bitmap = vec![vec![0; n]; n];
-        for i in 0..n {
-            for j in 0..n {
-                new_bitmap[i][n-j-1] = self.bitmap[i][j];
-            }
-        }
-        new_bitmap
+        self.bitmap.iter().map(|row| row.iter().rev().copied().collect()).collect()
     }
 
     fn generate_transforms(&self) -> Vec<Vec<Vec<i32>>> {
         let mut transforms = vec![self.bitmap.clone()];
         let cw1 = self.rotate_cw();
-        let cw2 = self.clone().rotate_cw();  // self.clone() to get a new Tile instance
-        let cw3 = self.clone().rotate_cw_twice();
+        let cw2 = self.rotate_cw_twice();
+        let cw3 = Tile { bitmap: cw2.clone(), ..self.clone() }.rotate_cw();
         let refl = self.reflect();
     
         match self.symmetry {
-            Symmetry::L => {
+            Some(Symmetry::L) => {
                 transforms.extend_from_slice(&[cw1, cw2, cw3]);
             },
-            Symmetry::T => {
+            Some(Symmetry::T) => {
                 transforms.extend_from_slice(&[cw1, cw2]);
             },
-            Symmetry::I => {
+            Some(Symmetry::I) => {
                 transforms.push(cw1);
             },
-            Symmetry::BackSlash => {
+            Some(Symmetry::BackSlash | Symmetry::ForwardSlash) => {
                 transforms.push(refl);
             },
-            Symmetry::F => {
+            Some(Symmetry::F) => {
                 transforms.extend_from_slice(&[refl, cw1, cw2, cw3]);
             },
-            Symmetry::X => {
+            Some(Symmetry::X) => {
                 transforms.push(refl);
             },
+            None => {},
         }
         transforms
     }
     
 }
 
+// The side of a bitmap facing `direction`, see `DIRECTIONS`
+fn edge(bitmap: &[Vec<i32>], direction: usize) -> Vec<i32> {
+    match direction {
+        0 => bitmap.first().cloned().unwrap_or_default(),
+        1 => bitmap.last().cloned().unwrap_or_default(),
+        2 => bitmap.iter().map(|row| row[0]).collect(),
+        _ => bitmap.iter().map(|row| row[row.len() - 1]).collect(),
+    }
+}
+
 impl Grid {
-    fn new(size: usize, tiles: Vec<Tile>, rules: HashMap<usize, Vec<usize>>, tile_transforms: HashMap<String, Vec<Vec<Vec<i32>>>>) -> Result<Self, &'static str> {
+    // Every distinct variant of `tiles` can go anywhere at first. Two variants may sit side
+    // by side if the pixels along their shared edge are the same.
+    fn new(size: usize, tiles: Vec<Tile>, seed: u64) -> Result<Self, &'static str> {
         if tiles.is_empty() {
             return Err("No tiles provided");
         }
-
+        let symmetries = tiles.iter().filter_map(|tile| tile.symmetry.clone().map(|symmetry| (tile.id, symmetry))).collect();
+        let tile_transforms: HashMap<String, Vec<Vec<Vec<i32>>>> = tiles.iter().map(|tile| (tile.name.clone(), tile.generate_transforms())).collect();
+
+        // Turns that give the same picture would only make a tile more likely
+        let mut variants = vec![];
+        let mut bitmaps: Vec<&Vec<Vec<i32>>> = vec![];
+        for tile in &tiles {
+            let transforms = &tile_transforms[&tile.name];
+            for (orientation, bitmap) in transforms.iter().enumerate() {
+                if !transforms[..orientation].contains(bitmap) {
+                    variants.push((tile.id, orientation));
+                    bitmaps.push(bitmap);
+                }
+            }
+        }
+        let compatible = (0..DIRECTIONS.len())
+            .map(|direction| {
+                let opposite = direction ^ 1;
+                bitmaps.iter()
+                    .map(|bitmap| {
+                        let side = edge(bitmap, direction);
+                        (0..bitmaps.len()).filter(|&other| edge(bitmaps[other], opposite) == side).collect()
+                    })
+                    .collect()
+            })
+            .collect();
+
+        let blank = Tile { value: None, bitmap: vec![], possible_values: (0..variants.len()).collect(), ..tiles[0].clone() };
         Ok(Self {
-            cells: (0..size)
-                .map(|_| (0..size)
-                .map(|_| {
-                    let tile_id = rand::random::<usize>() % tiles.len();
-                    let mut tile = tiles[tile_id].clone();
-                    tile.value = None; // All tiles start uncollapsed
-                    tile
-                }).collect())
-                .collect(),
-            rules,
+            cells: vec![vec![blank; size]; size],
             initial_collapse_done: false,
             tile_transforms,
+            symmetries,
+            variants,
+            compatible,
+            rng: StdRng::seed_from_u64(seed),
         })
     }
 
     fn entropy(&self, x: usize, y: usize) -> usize {
-        if let Some(value) = self.cells[x][y].value {
-            if value == 0 { usize::MAX } else { 0 }
-        } else {
-            self.cells[x][y].possible_values.len()
+        match self.cells[x][y].value {
+            Some(_) => 0,
+            None => self.cells[x][y].possible_values.len(),
         }
     }
 
-    fn id_to_name(&self, id: u32) -> &'static str {
-        match id {
-            1 => "plains",
-            2 => "forest",
-            3 => "mountains",
-            4 => "dessert",
-            5 => "shore",
-            6 => "ocean",
-            _ => panic!("Invalid ID"),
-        }
+    // The bitmap of the variant a collapsed cell got
+    fn cell_bitmap(&self, cell: &Tile) -> Option<&Vec<Vec<i32>>> {
+        self.tile_transforms.get(&cell.value?.to_string())?.get(cell.orientation)
+    }
+
+    // Picks one of the cell's variants and places it; the tile and its turn come together
+    fn collapse_cell(&mut self, x: usize, y: usize) {
+        let cell = &mut self.cells[x][y];
+        let variant = cell.possible_values[self.rng.gen_range(0..cell.possible_values.len())];
+        let (id, orientation) = self.variants[variant];
+        cell.value = Some(id);
+        cell.orientation = orientation;
+
+        // Remove other possibilities
+        cell.possible_values.clear();
+        cell.possible_values.push(variant);
     }
 
-    fn collapse(&mut self) -> bool {
+    // Collapses the next cell and returns where it is, None once nothing is left to collapse
+    fn collapse(&mut self) -> Option<(usize, usize)> {
         if !self.initial_collapse_done {
             let mid = self.cells.len() / 2;
             println!("Performing Initial collapse at {}, {}", mid, mid);
+            self.initial_collapse_done = true;
             if !self.cells[mid][mid].possible_values.is_empty() {
-                let idx = rand::thread_rng().gen_range(0..self.cells[mid][mid].possible_values.len());
-                self.cells[mid][mid].value = Some(self.cells[mid][mid].possible_values[idx]);
-                self.initial_collapse_done = true;
-                return true;
+                self.collapse_cell(mid, mid);
+                return Some((mid, mid));
             }
         }
         // Find the cell with the smallest non-zero entropy
@@ -306,92 +388,56 @@ impl Grid {
         }
         // Collapse that cell
         if min_entropy != usize::MAX {
-            let idx = rand::thread_rng().gen_range(0..self.cells[min_x][min_y].possible_values.len());
-            self.cells[min_x][min_y].value = Some(self.cells[min_x][min_y].possible_values[idx]);
-
-            // Remove other possibilities
-            self.cells[min_x][min_y].possible_values.clear();
-            let val = self.cells[min_x][min_y].value.unwrap();
-            self.cells[min_x][min_y].possible_values.push(val);
-            // self.cells[min_x][min_y].possible_values.push(self.cells[min_x][min_y].value.unwrap());
-            
-            return true;
+            self.collapse_cell(min_x, min_y);
+            return Some((min_x, min_y));
         }
-        false
+        None
     }
-    
-    fn propagate(&mut self) -> Result<(), ()> {
-        let dir = vec![(-1, 0), (1, 0), (0, -1), (0, 1)];  // Up, Down, Left, Right
-    
-        for i in 0..self.cells.len() {
-            for j in 0..self.cells[i].len() {
-                if let Some(value) = self.cells[i][j].value {
-                    for (dx, dy) in &dir {
-                        let nx = i as i32 + dx;
-                        let ny = j as i32 + dy;
-    
-                        if nx >= 0 && nx < self.cells.len() as i32 && ny >= 0 && ny < self.cells[i].len() as i32 {
-                            let nx = nx as usize;
-                            let ny = ny as usize;
-    
-                            // Get all transformations of the neighboring tile
-
-                            if let Some(neighbor_value) = self.cells[nx][ny].value {
-                                if let Some(neighbor_transforms) = self.tile_transforms.get(&neighbor_value.to_string()) {
-                                    let neighbor_transforms = self.tile_transforms[&self.cells[nx][ny].value.unwrap().to_string()].clone();
-                            
-                                    // Check each possible value of the current cell against all transformations of the neighbor
-                                    self.cells[i][j].possible_values.retain(|v| {
-                                        let allowed_values = self.rules.get(v).unwrap();
-                                    
-                                        neighbor_transforms.iter().flat_map(|x| x.iter().flat_map(|y| y.iter())).any(|t| {
-                                            let t_usize = *t as usize; // Convert i32 to usize
-                                            allowed_values.contains(&t_usize)
-                                        })
-                                    });
-                                } else {
-                                    //println!("No transformations found for the neighboring cell at ({}, {})", nx, ny);
-                                }
-                            } else {
-                                //println!("No value found for the neighboring cell at ({}, {})", nx, ny);
-                            }
 
+    // Narrows the neighbours of (x, y) to the variants that fit next to what is left there,
+    // and on outwards from every cell that lost a variant
+    fn propagate(&mut self, x: usize, y: usize) -> Result<(), ()> {
+        let mut stack = vec![(x, y)];
+        while let Some((i, j)) = stack.pop() {
+            for (direction, (dx, dy)) in DIRECTIONS.iter().enumerate() {
+                let nx = i as i32 + dx;
+                let ny = j as i32 + dy;
+                if nx < 0 || nx >= self.cells.len() as i32 || ny < 0 || ny >= self.cells[i].len() as i32 {
+                    continue;
+                }
+                let (nx, ny) = (nx as usize, ny as usize);
 
-    
-                            // Contradiction handling
-                            if self.cells[i][j].possible_values.is_empty() {
-                                println!("Contradiction found at ({}, {})", i, j);
-                                return Err(());
-                            }
-                        }
+                let mut allowed = vec![false; self.variants.len()];
+                for &variant in &self.cells[i][j].possible_values {
+                    for &other in &self.compatible[direction][variant] {
+                        allowed[other] = true;
                     }
                 }
+                let neighbour = &mut self.cells[nx][ny];
+                let before = neighbour.possible_values.len();
+                neighbour.possible_values.retain(|&variant| allowed[variant]);
+
+                // Contradiction handling
+                if neighbour.possible_values.is_empty() {
+                    println!("Contradiction found at ({}, {})", nx, ny);
+                    return Err(());
+                }
+                if neighbour.possible_values.len() < before {
+                    stack.push((nx, ny));
+                }
             }
         }
         Ok(())
     }
-    
-
-    // Add function to check for contradictions
-    fn has_contradiction(&self) -> bool {
-        self.cells.iter().flatten().any(|cell| cell.possible_values.is_empty() && cell.value.is_none())
-    }
-    
-    fn is_fully_collapsed(&self) -> bool {
-        // The grid is fully collapsed if every cell has a value
-        self.cells.iter().flatten().all(|cell| cell.value.is_some())
-    }
 
     // Modify run function to stop in case of contradictions
     fn run(&mut self) {
         let total_cells = self.cells.len() * self.cells[0].len();
         let mut pb = ProgressBar::new(total_cells as u64);
 
-        while !self.is_fully_collapsed() {
-            if self.collapse() {
-                pb.inc();
-            }
-            if let Err(()) = self.propagate() {  // Handle error from propagate
+        while let Some((x, y)) = self.collapse() {
+            pb.inc();
+            if let Err(()) = self.propagate(x, y) {  // Handle error from propagate
                 pb.finish_print("Grid collapsing ended with a contradiction.");
                 return;
             }
@@ -403,10 +449,34 @@ impl Grid {
 fn main() {
     println!("Initializing Program...");
     let current_dir = env::current_dir().unwrap();
-    let possible_values = vec![1, 2, 3, 4, 5, 6]; 
-    let (tiles, tile_transforms) = load_tiles(possible_values.clone(), &current_dir);
-    let rules = get_ruleset();
-    let grid_result = Grid::new(85, tiles, rules, tile_transforms);
+    let args: Vec<String> = env::args().collect();
+    let flag_value = |flag: &str| args.iter().position(|arg| arg == flag).and_then(|index| args.get(index + 1));
+    if let Some(index) = args.iter().position(|arg| arg == "--preview-sheet") {
+        // Contact sheet of every tile and its transforms instead of generating a map
+        let path = args.get(index + 1).map_or("tile_sheet.png", String::as_str);
+        if let Err(e) = preview::render_sheet(&current_dir, Path::new(path)) {
+            eprintln!("Failed to render tile sheet: {}", e);
+            process::exit(1);
+        }
+        return;
+    }
+    let seed = match flag_value("--seed").map(|seed| seed.parse::<u64>()) {
+        Some(Ok(seed)) => seed,
+        Some(Err(e)) => {
+            eprintln!("Invalid value for --seed: {}", e);
+            process::exit(1);
+        }
+        None => rand::random(),
+    };
+    let tiles = match load_tiles(&current_dir.join("tileset")) {
+        Ok(tiles) => tiles,
+        Err(e) => {
+            eprintln!("Failed to load tiles: {}", e);
+            process::exit(1);
+        }
+    };
+    println!("Using seed {}", seed);
+    let grid_result = Grid::new(85, tiles, seed);
 
     let mut grid = match grid_result {
         Ok(g) => g,
@@ -417,10 +487,19 @@ fn main() {
     };
 
     grid.run();
+    // Tiled maps with each cell's variant as flip flags, written before stitching
+    if let Some(path) = flag_value("--tmx") {
+        if let Err(e) = tiled::export_tmx(&grid, Path::new(path)) {
+            eprintln!("Failed to export Tiled map: {}", e);
+        }
+    }
+    if let Some(path) = flag_value("--tiled-json") {
+        if let Err(e) = tiled::export_tiled_json(&grid, Path::new(path)) {
+            eprintln!("Failed to export Tiled JSON map: {}", e);
+        }
+    }
     match stitch_images(&grid) {
         Ok(_) => println!("Image stitching completed successfully."),
         Err(e) => println!("Failed to stitch images: {:?}", e),
     }
 }
-
-
@@ -0,0 +1,98 @@
+use image::{imageops, Rgba, RgbaImage};
+use image::imageops::FilterType;
+use std::path::Path;
+use crate::{font, load_tiles, unpack_bitmap, Symmetry, Transform};
+
+// Tiles are drawn this many times their size
+const SCALE: u32 = 2;
+const MARGIN: u32 = 8;
+const GAP: u32 = 8;
+const LINE: u32 = font::GLYPH_HEIGHT + 4;
+// Room for the tile details left of its variants
+const LABEL_WIDTH: u32 = 120;
+
+const BACKGROUND: Rgba<u8> = Rgba([24, 24, 24, 255]);
+const SEPARATOR: Rgba<u8> = Rgba([60, 60, 60, 255]);
+const TEXT: Rgba<u8> = Rgba([230, 230, 230, 255]);
+const DUPLICATE: Rgba<u8> = Rgba([230, 150, 40, 255]);
+
+// One row of the sheet: a tile and the bitmaps `Tile::generate_transforms` made from it
+struct SheetRow {
+    id: usize,
+    name: String,
+    class: String,
+    weight: f32,
+    variants: Vec<(&'static str, Vec<Vec<i32>>)>,
+}
+
+// Every tile `load_tiles` finds in `dir`, with the variants the solver picks from
+fn load_rows(dir: &Path) -> Result<Vec<SheetRow>, String> {
+    let rows = load_tiles(dir)?.into_iter()
+        .map(|tile| {
+            let variants = tile.transforms().iter().map(Transform::name).zip(tile.generate_transforms()).collect();
+            let class = tile.symmetry.as_ref().map_or("", Symmetry::class).to_string();
+            SheetRow { id: tile.id, name: tile.name, class, weight: tile.weight, variants }
+        })
+        .collect();
+    Ok(rows)
+}
+
+fn unpack(bitmap: &[Vec<i32>]) -> RgbaImage {
+    let size = bitmap.len() as u32;
+    let image = unpack_bitmap(bitmap);
+    imageops::resize(&image, size * SCALE, size * SCALE, FilterType::Nearest)
+}
+
+// Contact sheet of every tile in `<dir>/tileset` and each variant of it, labelled with
+// id, name, symmetry class and weight. Variants identical to an earlier one of the same
+// tile are marked, they usually mean the symmetry class or a transform is off.
+pub fn render_sheet(dir: &Path, path: &Path) -> Result<(), String> {
+    let rows = load_rows(&dir.join("tileset"))?;
+    if rows.is_empty() {
+        return Err("No tiles found".to_string());
+    }
+    let tile_size = rows.iter().map(|row| row.variants[0].1.len() as u32).max().unwrap_or(0) * SCALE;
+    let column = tile_size.max(font::text_width("= original")) + GAP;
+    let row_height = (tile_size + LINE * 2).max(LINE * 5) + GAP;
+    let columns = rows.iter().map(|row| row.variants.len() as u32).max().unwrap_or(0);
+
+    let title = "Tile variants, = marks a copy of an earlier variant";
+    let width = (LABEL_WIDTH + columns * column).max(font::text_width(title)) + MARGIN * 2;
+    let height = MARGIN + LINE + rows.len() as u32 * row_height + MARGIN;
+    let mut canvas = RgbaImage::from_pixel(width, height, BACKGROUND);
+    font::draw_text(&mut canvas, MARGIN, MARGIN, title, TEXT);
+
+    for (index, row) in rows.iter().enumerate() {
+        let top = MARGIN + LINE + index as u32 * row_height;
+        for x in MARGIN..width - MARGIN {
+            canvas.put_pixel(x, top, SEPARATOR);
+        }
+        let class = if row.class.is_empty() { "none" } else { row.class.as_str() };
+        let details = [
+            format!("tile {}", row.id),
+            format!("name {}", row.name),
+            format!("symmetry {}", class),
+            format!("weight {:.2}", row.weight),
+            format!("{} variant(s)", row.variants.len()),
+        ];
+        for (line, text) in details.iter().enumerate() {
+            font::draw_text(&mut canvas, MARGIN, top + GAP / 2 + line as u32 * LINE, text, TEXT);
+        }
+
+        let mut duplicates = 0;
+        for (position, (name, bitmap)) in row.variants.iter().enumerate() {
+            let left = MARGIN + LABEL_WIDTH + position as u32 * column;
+            imageops::overlay(&mut canvas, &unpack(bitmap), left, top + GAP / 2);
+            font::draw_text(&mut canvas, left, top + GAP / 2 + tile_size + 3, name, TEXT);
+            if let Some((earlier, _)) = row.variants[..position].iter().find(|(_, other)| other == bitmap) {
+                font::draw_text(&mut canvas, left, top + GAP / 2 + tile_size + 3 + LINE, &format!("= {}", earlier), DUPLICATE);
+                duplicates += 1;
+            }
+        }
+        println!("Tile {} ({}): {} variant(s), {} duplicate(s)", row.id, class, row.variants.len(), duplicates);
+    }
+
+    canvas.save(path).map_err(|e| format!("{:?}", e))?;
+    println!("Saved tile sheet to {}", path.display());
+    Ok(())
+}
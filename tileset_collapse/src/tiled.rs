@@ -0,0 +1,134 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use crate::tiled_map::{self, TiledMap, TiledTile};
+use crate::{Grid, Tile, Transform};
+
+// Tiled stores flips in the top bits of each global tile id
+const FLIPPED_HORIZONTALLY: u32 = 0x8000_0000;
+const FLIPPED_VERTICALLY: u32 = 0x4000_0000;
+const FLIPPED_DIAGONALLY: u32 = 0x2000_0000;
+
+// Tiled applies the diagonal flip first, then the horizontal and the vertical one,
+// so a clockwise quarter turn is a diagonal flip followed by a horizontal one
+fn transform_flags(transform: Transform) -> u32 {
+    match transform {
+        Transform::Original => 0,
+        Transform::Cw1 => FLIPPED_DIAGONALLY | FLIPPED_HORIZONTALLY,
+        Transform::Cw2 => FLIPPED_HORIZONTALLY | FLIPPED_VERTICALLY,
+        Transform::Cw3 => FLIPPED_DIAGONALLY | FLIPPED_VERTICALLY,
+        Transform::Reflect => FLIPPED_HORIZONTALLY,
+    }
+}
+
+// The variant a collapsed cell got, as picked by `Grid::collapse_cell`
+fn cell_transform(grid: &Grid, cell: &Tile) -> Transform {
+    cell.value.and_then(|value| grid.symmetries.get(&value))
+        .and_then(|symmetry| symmetry.transforms().get(cell.orientation).copied())
+        .unwrap_or(Transform::Original)
+}
+
+// The same images `stitch_images` draws
+fn tileset_tiles(grid: &Grid, map_path: &Path) -> io::Result<Vec<TiledTile>> {
+    let mut ids: Vec<usize> = grid.variants.iter().map(|&(id, _)| id).collect();
+    ids.dedup();
+    let tiles = ids.into_iter().map(|id| (id, id.to_string(), PathBuf::from("tileset").join(format!("{}.gif", id))));
+    tiled_map::tileset_tiles(tiles, map_path)
+}
+
+// Global tile ids with the flags of each cell's variant, row-major, 0 for uncollapsed cells
+fn layer_data(grid: &Grid, tiles: &[TiledTile]) -> Vec<u32> {
+    grid.cells.iter().flatten()
+        .map(|cell| match cell.value.and_then(|v| tiles.iter().position(|t| t.id == v)) {
+            Some(index) => (index as u32 + 1) | transform_flags(cell_transform(grid, cell)),
+            None => 0,
+        })
+        .collect()
+}
+
+fn tiled_map(grid: &Grid, path: &Path) -> io::Result<TiledMap> {
+    let tiles = tileset_tiles(grid, path)?;
+    Ok(TiledMap {
+        tileset: "tileset_collapse",
+        layer: "tiles",
+        width: grid.cells.first().map_or(0, |row| row.len()),
+        height: grid.cells.len(),
+        hex: false,
+        data: layer_data(grid, &tiles),
+        tiles,
+    })
+}
+
+// Writes an orthogonal .tmx map with an image collection tileset pointing at `tileset/`
+pub fn export_tmx(grid: &Grid, path: &Path) -> io::Result<()> {
+    tiled_map(grid, path)?.write_tmx(path)
+}
+
+// Same map as `export_tmx`, in Tiled's JSON map format
+pub fn export_tiled_json(grid: &Grid, path: &Path) -> io::Result<()> {
+    tiled_map(grid, path)?.write_json(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, fs};
+    use crate::load_tiles;
+
+    // Values of every `source="..."` attribute in a .tmx
+    fn sources(tmx: &str) -> Vec<String> {
+        tmx.split(r#"source=""#).skip(1).map(|rest| rest[..rest.find('"').unwrap()].to_string()).collect()
+    }
+
+    // What Tiled draws for a tile with `flags`: diagonal flip first, then horizontal, then vertical
+    fn flipped(bitmap: &[Vec<i32>], flags: u32) -> Vec<Vec<i32>> {
+        let mut result: Vec<Vec<i32>> = bitmap.to_vec();
+        if flags & FLIPPED_DIAGONALLY != 0 {
+            result = (0..result.len()).map(|x| result.iter().map(|row| row[x]).collect()).collect();
+        }
+        if flags & FLIPPED_HORIZONTALLY != 0 {
+            result.iter_mut().for_each(|row| row.reverse());
+        }
+        if flags & FLIPPED_VERTICALLY != 0 {
+            result.reverse();
+        }
+        result
+    }
+
+    #[test]
+    fn flags_draw_the_variant_that_was_stitched() {
+        let tiles = load_tiles(&Path::new(env!("CARGO_MANIFEST_DIR")).join("tileset")).unwrap();
+        for tile in &tiles {
+            for (transform, bitmap) in tile.transforms().iter().zip(tile.generate_transforms()) {
+                assert!(flipped(&tile.bitmap, transform_flags(*transform)) == bitmap, "tile {} {}", tile.id, transform.name());
+            }
+        }
+    }
+
+    #[test]
+    fn exported_maps_reference_images_that_exist() {
+        let crate_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+        env::set_current_dir(crate_dir).unwrap();
+        let mut grid = Grid::new(6, load_tiles(&crate_dir.join("tileset")).unwrap(), 1).unwrap();
+        grid.run();
+
+        let map_dir = env::temp_dir().join(format!("tileset_collapse_{}_tiled", std::process::id()));
+        fs::create_dir_all(&map_dir).unwrap();
+        let tmx_path = map_dir.join("map.tmx");
+        let json_path = map_dir.join("map.json");
+        export_tmx(&grid, &tmx_path).unwrap();
+        export_tiled_json(&grid, &json_path).unwrap();
+        let tmx = fs::read_to_string(&tmx_path).unwrap();
+        let json: serde_json::Value = serde_json::from_str(&fs::read_to_string(&json_path).unwrap()).unwrap();
+
+        let tmx_images = sources(&tmx);
+        let json_images: Vec<String> = json["tilesets"][0]["tiles"].as_array().unwrap().iter()
+            .map(|tile| tile["image"].as_str().unwrap().to_string())
+            .collect();
+        assert!(!tmx_images.is_empty());
+        assert_eq!(tmx_images, json_images);
+        for image in &tmx_images {
+            assert!(map_dir.join(image).is_file(), "{} does not resolve from {}", image, map_dir.display());
+        }
+        fs::remove_dir_all(&map_dir).unwrap();
+    }
+}
@@ -0,0 +1,65 @@
+use image::codecs::gif::{GifEncoder, Repeat};
+use image::{Delay, Frame, ImageError, ImageResult};
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use crate::Grid;
+use crate::render::{self, TileImages};
+
+// Frames of a recording when no interval is given, whatever the grid size. Each frame of a
+// large map is a full image, so recording every few cells soon makes huge, slow GIFs.
+const DEFAULT_FRAMES: usize = 40;
+
+// Where recorded frames go: a single animated GIF, or numbered PNGs in a directory
+pub enum Recorder {
+    Gif(Box<GifEncoder<BufWriter<File>>>, Delay),
+    Frames { dir: PathBuf, count: usize },
+}
+
+impl Recorder {
+    // A path ending in `.gif` records an animation, anything else is used as a frame directory
+    pub fn new(path: &Path, delay_ms: u32) -> ImageResult<Self> {
+        if path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("gif")) {
+            let file = File::create(path).map_err(ImageError::IoError)?;
+            let mut encoder = GifEncoder::new_with_speed(BufWriter::new(file), 10);
+            encoder.set_repeat(Repeat::Infinite)?;
+            Ok(Recorder::Gif(Box::new(encoder), Delay::from_numer_denom_ms(delay_ms, 1)))
+        } else {
+            fs::create_dir_all(path).map_err(ImageError::IoError)?;
+            Ok(Recorder::Frames { dir: path.to_path_buf(), count: 0 })
+        }
+    }
+
+    pub fn record(&mut self, grid: &Grid, images: &TileImages) -> ImageResult<()> {
+        let frame = render::render_grid(grid, images);
+        match self {
+            Recorder::Gif(encoder, delay) => encoder.encode_frame(Frame::from_parts(frame, 0, 0, *delay)),
+            Recorder::Frames { dir, count } => {
+                *count += 1;
+                frame.save(dir.join(format!("frame_{:05}.png", count)))
+            }
+        }
+    }
+}
+
+// Solves the grid, recording a frame every `every` collapsed cells plus the final state,
+// by default about `DEFAULT_FRAMES` in total. Returns the number of frames written.
+pub fn record_run(grid: &mut Grid, path: &Path, every: Option<usize>, delay_ms: u32) -> ImageResult<usize> {
+    let open_cells = grid.remaining_cells();
+    let every = every.unwrap_or(open_cells / DEFAULT_FRAMES).max(1);
+    println!("Recording a frame every {} collapsed cells", every);
+    let images = TileImages::load(grid)?;
+    let mut recorder = Recorder::new(path, delay_ms)?;
+    let mut frames = 0;
+    let mut result = Ok(());
+
+    grid.run_observed(every, |grid| {
+        if result.is_ok() {
+            result = recorder.record(grid, &images);
+            frames += 1;
+        }
+    });
+    result?;
+    println!("Recorded {} frames to {}", frames, path.display());
+    Ok(frames)
+}
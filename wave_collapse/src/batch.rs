@@ -0,0 +1,111 @@
+use image::{ImageError, ImageResult};
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+use crate::{export, render, Grid};
+use crate::render::TileImages;
+
+// How one map of a batch turned out
+struct BatchResult {
+    seed: u64,
+    complete: bool,
+    steps: usize,
+    backtracks: usize,
+    contradictions: usize,
+    time: Duration,
+}
+
+// Solves a copy of `template` with `seed` and writes `seed_<seed>.png` and `.json` into `dir`
+fn generate(template: &Grid, seed: u64, images: &TileImages, dir: &Path) -> ImageResult<BatchResult> {
+    let start = Instant::now();
+    let mut grid = template.clone();
+    grid.quiet = true;
+    grid.reseed(seed);
+    let complete = grid.solve();
+    let time = start.elapsed();
+
+    render::render_grid(&grid, images).save(dir.join(format!("seed_{}.png", seed)))?;
+    export::export_json(&grid, &dir.join(format!("seed_{}.json", seed))).map_err(ImageError::IoError)?;
+
+    Ok(BatchResult {
+        seed,
+        complete,
+        steps: grid.stats.steps,
+        backtracks: grid.stats.backtracks.iter().flatten().sum(),
+        contradictions: grid.stats.contradictions.iter().flatten().sum(),
+        time,
+    })
+}
+
+fn write_summary(results: &[BatchResult], path: &Path) -> ImageResult<()> {
+    let mut writer = BufWriter::new(File::create(path).map_err(ImageError::IoError)?);
+    let mut write = || -> std::io::Result<()> {
+        writeln!(writer, "seed,result,steps,backtracks,contradictions,millis")?;
+        for result in results {
+            let outcome = if result.complete { "complete" } else { "contradiction" };
+            writeln!(writer, "{},{},{},{},{},{}", result.seed, outcome, result.steps, result.backtracks,
+                result.contradictions, result.time.as_millis())?;
+        }
+        writer.flush()
+    };
+    write().map_err(ImageError::IoError)
+}
+
+// Generates `count` maps from `template` with the seeds following its own, spread over
+// `threads` workers, then prints a summary and writes it to `summary.csv` in `dir`
+pub fn run_batch(template: &Grid, count: usize, threads: usize, dir: &Path) -> ImageResult<()> {
+    fs::create_dir_all(dir).map_err(ImageError::IoError)?;
+    let images = TileImages::load(template)?;
+    let threads = threads.clamp(1, count.max(1));
+    println!("Generating {} maps of {}x{} on {} threads, seeds {} to {}", count, template.cells.len(),
+        template.cells.first().map_or(0, |row| row.len()), threads, template.seed, template.seed.wrapping_add(count.saturating_sub(1) as u64));
+
+    let start = Instant::now();
+    let next = AtomicUsize::new(0);
+    let mut results = thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads)
+            .map(|_| scope.spawn(|| {
+                let mut results = vec![];
+                loop {
+                    let index = next.fetch_add(1, Ordering::Relaxed);
+                    if index >= count {
+                        return Ok(results);
+                    }
+                    let result = generate(template, template.seed.wrapping_add(index as u64), &images, dir)?;
+                    println!("Seed {}: {} in {:?}", result.seed,
+                        if result.complete { "complete" } else { "contradiction" }, result.time);
+                    results.push(result);
+                }
+            }))
+            .collect();
+        workers.into_iter()
+            .map(|worker| worker.join().expect("Batch worker panicked"))
+            .collect::<ImageResult<Vec<Vec<BatchResult>>>>()
+    })?.into_iter().flatten().collect::<Vec<_>>();
+    let elapsed = start.elapsed();
+    // In seed order, counting from the template even when the seeds wrapped around
+    results.sort_by_key(|result| result.seed.wrapping_sub(template.seed));
+
+    let complete = results.iter().filter(|result| result.complete).count();
+    let times: Vec<Duration> = results.iter().map(|result| result.time).collect();
+    let total: Duration = times.iter().sum();
+    println!("Batch finished in {:?}", elapsed);
+    println!("  complete:       {}", complete);
+    println!("  contradictions: {}", results.len() - complete);
+    if !results.is_empty() {
+        println!("  solve time:     min {:?}, mean {:?}, max {:?}", times.iter().min().unwrap(),
+            total / results.len() as u32, times.iter().max().unwrap());
+    }
+    let failed: Vec<String> = results.iter().filter(|result| !result.complete).map(|result| result.seed.to_string()).collect();
+    if !failed.is_empty() {
+        println!("  failed seeds:   {}", failed.join(", "));
+    }
+
+    let summary_path = dir.join("summary.csv");
+    write_summary(&results, &summary_path)?;
+    println!("Wrote batch summary to {}", summary_path.display());
+    Ok(())
+}
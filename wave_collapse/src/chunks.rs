@@ -0,0 +1,190 @@
+use std::collections::HashMap;
+use crate::Grid;
+
+// Seeds tried for a chunk before giving up on it
+const MAX_ATTEMPTS: u64 = 10;
+
+// Tile ids of a generated chunk, indexed [row][column]
+pub type Chunk = Vec<Vec<usize>>;
+// (cx, cy) in chunks, cx grows to the right and cy downwards
+pub type ChunkCoord = (i64, i64);
+
+// Indices into `Grid::compatible` of a square grid
+const NORTH: usize = 0;
+const SOUTH: usize = 1;
+const WEST: usize = 2;
+const EAST: usize = 3;
+
+// An unbounded map generated one square chunk at a time, on request and in any order.
+// Cells along a new chunk's edge only get tiles the ruleset allows next to the already
+// generated neighbour chunks, so seams look like any other part of the map. Chunk (cx, cy)
+// covers columns cx * size.. and rows cy * size.. of the world.
+pub struct World {
+    seed: u64,
+    chunk_size: usize,
+    // An empty chunk with the tiles, weights and rules of the world, copied for every chunk
+    template: Grid,
+    chunks: HashMap<ChunkCoord, Chunk>,
+}
+
+impl World {
+    // `template` has to be an empty, square, non-wrapping grid
+    pub fn new(seed: u64, template: Grid) -> Self {
+        Self {
+            seed,
+            chunk_size: template.cells.len(),
+            template,
+            chunks: HashMap::new(),
+        }
+    }
+
+    // A chunk that was generated already
+    pub fn get(&self, cx: i64, cy: i64) -> Option<&Chunk> {
+        self.chunks.get(&(cx, cy))
+    }
+
+    // The chunk at (cx, cy), generated first if it does not exist yet
+    pub fn chunk(&mut self, cx: i64, cy: i64) -> Result<&Chunk, &'static str> {
+        if !self.chunks.contains_key(&(cx, cy)) {
+            let chunk = self.generate(cx, cy)?;
+            self.chunks.insert((cx, cy), chunk);
+        }
+        Ok(&self.chunks[&(cx, cy)])
+    }
+
+    // Same world seed and chunk coordinates always give the same seed
+    fn chunk_seed(&self, cx: i64, cy: i64, attempt: u64) -> u64 {
+        self.seed
+            ^ (cx as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15)
+            ^ (cy as u64).wrapping_mul(0xC2B2_AE3D_27D4_EB4F)
+            ^ attempt.wrapping_mul(0x1656_67B1_9E37_79F9)
+    }
+
+    // Ids that may sit on a cell with `id` to its `direction`; inside a grid both tiles
+    // constrain each other
+    fn allowed_next_to(&self, id: usize, direction: usize) -> Vec<usize> {
+        let grid = &self.template;
+        let Ok(neighbour) = grid.ids.binary_search(&id) else {
+            return vec![];
+        };
+        let back = grid.topology.opposite(direction);
+        grid.compatible[back][neighbour].iter()
+            .filter(|&index| grid.compatible[direction][index].contains(neighbour))
+            .map(|index| grid.ids[index])
+            .collect()
+    }
+
+    // Ids each edge cell of chunk (cx, cy) may take next to the neighbour chunks that exist
+    fn border_constraints(&self, cx: i64, cy: i64) -> HashMap<(usize, usize), Vec<usize>> {
+        let last = self.chunk_size - 1;
+        let mut constraints: HashMap<(usize, usize), Vec<usize>> = HashMap::new();
+        let mut restrict = |cell: (usize, usize), id: usize, direction: usize| {
+            let allowed = self.allowed_next_to(id, direction);
+            constraints.entry(cell)
+                .and_modify(|current| current.retain(|v| allowed.contains(v)))
+                .or_insert(allowed);
+        };
+
+        for i in 0..self.chunk_size {
+            if let Some(above) = self.get(cx, cy - 1) {
+                restrict((0, i), above[last][i], NORTH);
+            }
+            if let Some(below) = self.get(cx, cy + 1) {
+                restrict((last, i), below[0][i], SOUTH);
+            }
+            if let Some(left) = self.get(cx - 1, cy) {
+                restrict((i, 0), left[i][last], WEST);
+            }
+            if let Some(right) = self.get(cx + 1, cy) {
+                restrict((i, last), right[i][0], EAST);
+            }
+        }
+        constraints
+    }
+
+    fn generate(&self, cx: i64, cy: i64) -> Result<Chunk, &'static str> {
+        let borders = self.border_constraints(cx, cy);
+        for attempt in 0..MAX_ATTEMPTS {
+            let mut grid = self.template.clone();
+            grid.reseed(self.chunk_seed(cx, cy, attempt));
+            grid.quiet = true;
+            if !borders.iter().all(|(&(x, y), allowed)| grid.constrain(x, y, allowed)) {
+                return Err("Neighbouring chunks leave no tile for a border cell");
+            }
+            if grid.solve() {
+                println!("Generated chunk ({}, {}) after {} attempt(s)", cx, cy, attempt + 1);
+                return Ok(grid.cells.iter()
+                    .map(|row| row.iter().map(|cell| cell.value.unwrap_or(0)).collect())
+                    .collect());
+            }
+        }
+        Err("Chunk ran into a contradiction with every seed")
+    }
+
+    // Generates every chunk between two corners, row by row, and joins them into one grid
+    // with the tiles and rules of the template. Chunks that cannot be generated are left
+    // uncollapsed.
+    pub fn area(&mut self, (cx0, cy0): ChunkCoord, (cx1, cy1): ChunkCoord) -> Grid {
+        let (cx0, cx1) = (cx0.min(cx1), cx0.max(cx1));
+        let (cy0, cy1) = (cy0.min(cy1), cy0.max(cy1));
+        let columns = (cx1 - cx0 + 1) as usize * self.chunk_size;
+        let rows = (cy1 - cy0 + 1) as usize * self.chunk_size;
+        let mut values = vec![vec![None; columns]; rows];
+
+        for cy in cy0..=cy1 {
+            for cx in cx0..=cx1 {
+                let chunk_size = self.chunk_size;
+                match self.chunk(cx, cy) {
+                    Ok(chunk) => {
+                        for (y, row) in chunk.iter().enumerate() {
+                            for (x, &id) in row.iter().enumerate() {
+                                values[(cy - cy0) as usize * chunk_size + y][(cx - cx0) as usize * chunk_size + x] = Some(id);
+                            }
+                        }
+                    }
+                    Err(e) => println!("Skipping chunk ({}, {}): {}", cx, cy, e),
+                }
+            }
+        }
+        let mut grid = Grid::from_values(values, self.template.rules.clone(), self.seed);
+        grid.tileset = self.template.tileset.clone();
+        grid.set_weights(self.template.weights.clone());
+        // Same ids and square directions, so edge rules carry over as they are
+        grid.compatible = self.template.compatible.clone();
+        grid
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tileset::Manifest;
+    use crate::topology::TopologyKind;
+
+    fn fits(world: &World, id: usize, neighbour: usize, direction: usize) -> bool {
+        world.allowed_next_to(neighbour, direction).contains(&id)
+    }
+
+    #[test]
+    fn neighbouring_chunks_agree_on_their_border() {
+        let template = Manifest::terrain().grid(8, 8, &TopologyKind::Square, 5).unwrap();
+        let mut world = World::new(5, template);
+        // Out of order, so both the left and the right chunk get constrained by the other
+        for (cx, cy) in [(1, 0), (0, 0), (0, 1), (2, 0)] {
+            world.chunk(cx, cy).unwrap();
+        }
+
+        let last = world.chunk_size - 1;
+        for (left, right) in [((0, 0), (1, 0)), ((1, 0), (2, 0))] {
+            let (left, right) = (world.get(left.0, left.1).unwrap(), world.get(right.0, right.1).unwrap());
+            for row in 0..world.chunk_size {
+                assert!(fits(&world, right[row][0], left[row][last], WEST), "row {}", row);
+                assert!(fits(&world, left[row][last], right[row][0], EAST), "row {}", row);
+            }
+        }
+        let (above, below) = (world.get(0, 0).unwrap(), world.get(0, 1).unwrap());
+        for column in 0..world.chunk_size {
+            assert!(fits(&world, below[0][column], above[last][column], NORTH), "column {}", column);
+        }
+    }
+}
@@ -0,0 +1,326 @@
+use std::collections::{HashSet, VecDeque};
+use crate::{Elimination, Grid};
+use crate::domain::Domain;
+
+// Tiles a path can cross unless told otherwise: plains, forest, dessert and shore
+pub const DEFAULT_WALKABLE: [usize; 4] = [1, 2, 4, 5];
+
+// Largest components listed by `report`
+const REPORTED_COMPONENTS: usize = 10;
+
+// Cells searched around a closed off cell for another way between its neighbours,
+// before all components are labelled again
+const LOCAL_SEARCH: usize = 256;
+
+// Global constraint that walkable tiles stay reachable from each other. Cells that may
+// still become walkable are the possible paths and are kept labelled by component. A
+// collapse that splits what must stay connected is a contradiction, and with `single`
+// set, cells that can no longer reach the walkable area lose their walkable options.
+#[derive(Clone)]
+pub struct Connectivity {
+    walkable: Domain,
+    // Every walkable cell joins one component
+    single: bool,
+    // Cells that must reach each other, forced to be walkable
+    points: Vec<(usize, usize)>,
+    // `label` of the grid as of the last check, empty before the first
+    labels: Vec<Vec<Option<usize>>>,
+    // Component of the walkable area, once there is one
+    joined: Option<usize>,
+}
+
+impl Connectivity {
+    fn may_walk(&self, grid: &Grid, x: usize, y: usize) -> bool {
+        !grid.cells[x][y].domain.intersection(&self.walkable).is_empty()
+    }
+
+    fn walks(&self, grid: &Grid, x: usize, y: usize) -> bool {
+        let cell = &grid.cells[x][y];
+        cell.value.is_some() && self.may_walk(grid, x, y)
+    }
+
+    fn counts(&self, grid: &Grid, x: usize, y: usize, collapsed_only: bool) -> bool {
+        if collapsed_only { self.walks(grid, x, y) } else { self.may_walk(grid, x, y) }
+    }
+
+    // Component number of each cell that may still be walkable, or is walkable already
+    fn label(&self, grid: &Grid, collapsed_only: bool) -> Vec<Vec<Option<usize>>> {
+        let mut labels: Vec<Vec<Option<usize>>> = grid.cells.iter().map(|row| vec![None; row.len()]).collect();
+        let mut component = 0;
+        for x in 0..grid.cells.len() {
+            for y in 0..grid.cells[x].len() {
+                if labels[x][y].is_some() || !self.counts(grid, x, y, collapsed_only) {
+                    continue;
+                }
+                labels[x][y] = Some(component);
+                let mut stack = vec![(x, y)];
+                while let Some((i, j)) = stack.pop() {
+                    for (nx, ny) in grid.neighbours(i, j) {
+                        if labels[nx][ny].is_none() && self.counts(grid, nx, ny, collapsed_only) {
+                            labels[nx][ny] = Some(component);
+                            stack.push((nx, ny));
+                        }
+                    }
+                }
+                component += 1;
+            }
+        }
+        labels
+    }
+
+    // Checks the grid after a collapse and prunes cells cut off from the walkable area.
+    // Only the cells narrowed since the last check are looked at, the whole grid is labelled
+    // again when one of them may have split a component. Returns false if what has to stay
+    // connected no longer can.
+    pub fn enforce(&mut self, grid: &mut Grid) -> bool {
+        let narrowed = std::mem::take(&mut grid.narrowed);
+        // Cells only ever lose options while solving, a regenerated region gains them back
+        if self.labels.is_empty() || narrowed.iter().any(|&(x, y)| self.labels[x][y].is_none() && self.may_walk(grid, x, y)) {
+            return self.relabel(grid);
+        }
+
+        let mut closed = HashSet::new();
+        for &(x, y) in &narrowed {
+            if self.labels[x][y].is_some() && !self.may_walk(grid, x, y) {
+                self.labels[x][y] = None;
+                closed.insert((x, y));
+            }
+        }
+        if !self.still_connected(grid, &closed) {
+            return self.relabel(grid);
+        }
+
+        // The first walkable cell decides which component is the walkable area
+        if self.single && narrowed.iter().any(|&(x, y)| self.walks(grid, x, y) && self.labels[x][y] != self.joined) {
+            return self.relabel(grid);
+        }
+        true
+    }
+
+    // True if the cells that may walk around every patch of `closed` cells still reach each
+    // other within `LOCAL_SEARCH` cells, so closing them off split no component
+    fn still_connected(&self, grid: &Grid, closed: &HashSet<(usize, usize)>) -> bool {
+        let mut seen = HashSet::new();
+        for &start in closed {
+            if !seen.insert(start) {
+                continue;
+            }
+            // The patch of closed cells around `start` and the open cells bordering it
+            let mut patch = vec![start];
+            let mut border = HashSet::new();
+            while let Some((i, j)) = patch.pop() {
+                for (nx, ny) in grid.neighbours(i, j) {
+                    if closed.contains(&(nx, ny)) {
+                        if seen.insert((nx, ny)) {
+                            patch.push((nx, ny));
+                        }
+                    } else if self.labels[nx][ny].is_some() {
+                        border.insert((nx, ny));
+                    }
+                }
+            }
+
+            let Some(&first) = border.iter().next() else {
+                continue;
+            };
+            let mut reached = HashSet::from([first]);
+            let mut queue = VecDeque::from([first]);
+            let mut found = 1;
+            while found < border.len() {
+                let Some((i, j)) = queue.pop_front() else {
+                    return false;
+                };
+                if reached.len() > LOCAL_SEARCH {
+                    return false;
+                }
+                for (nx, ny) in grid.neighbours(i, j) {
+                    if self.labels[nx][ny].is_some() && reached.insert((nx, ny)) {
+                        found += border.contains(&(nx, ny)) as usize;
+                        queue.push_back((nx, ny));
+                    }
+                }
+            }
+        }
+        true
+    }
+
+    // Labels every component again and checks them as a whole
+    fn relabel(&mut self, grid: &mut Grid) -> bool {
+        self.labels = self.label(grid, false);
+        self.joined = None;
+        for &(x, y) in &self.points {
+            match self.labels[x][y] {
+                Some(label) if self.joined.is_none_or(|joined| joined == label) => self.joined = Some(label),
+                _ => return false,
+            }
+        }
+        if !self.single {
+            return true;
+        }
+
+        for (x, row) in self.labels.iter().enumerate() {
+            for (y, &label) in row.iter().enumerate() {
+                if !self.walks(grid, x, y) {
+                    continue;
+                }
+                match self.joined {
+                    Some(joined) if label != Some(joined) => return false,
+                    _ => self.joined = label,
+                }
+            }
+        }
+        let Some(joined) = self.joined else {
+            return true;
+        };
+
+        let blocked = Domain::full(grid.ids.len()).difference(&self.walkable);
+        for x in 0..self.labels.len() {
+            for y in 0..self.labels[x].len() {
+                let label = self.labels[x][y];
+                if label.is_none() || label == Some(joined) {
+                    continue;
+                }
+                grid.narrow(x, y, &blocked, Elimination::Unreachable);
+                grid.changed.push((x, y));
+                self.labels[x][y] = None;
+                if grid.cells[x][y].domain.is_empty() {
+                    return false;
+                }
+            }
+        }
+        // Already accounted for in the labels
+        grid.narrowed.clear();
+        true
+    }
+}
+
+// Tile ids from `--walkable 1,2,4,5`
+pub fn parse_ids(ids: &str) -> Result<Vec<usize>, String> {
+    ids.split(',')
+        .map(|id| id.trim().parse().map_err(|_| format!("Invalid tile id: {}", id)))
+        .collect()
+}
+
+// Points from `--connect row,col;row,col`
+pub fn parse_points(points: &str) -> Result<Vec<(usize, usize)>, String> {
+    points.split(';')
+        .map(|point| {
+            let coords: Vec<usize> = point.split(',')
+                .map(|n| n.trim().parse().map_err(|_| format!("Invalid point: {}", point)))
+                .collect::<Result<_, _>>()?;
+            match coords[..] {
+                [x, y] => Ok((x, y)),
+                _ => Err(format!("Point needs row,col: {}", point)),
+            }
+        })
+        .collect()
+}
+
+// Makes `grid` keep its walkable tiles connected while solving, all of them if `single`
+// is set, otherwise just `points`, which are limited to walkable tiles right away
+pub fn constrain(grid: &mut Grid, walkable_ids: &[usize], points: Vec<(usize, usize)>, single: bool) -> Result<(), String> {
+    let mut walkable = Domain::empty();
+    for &id in walkable_ids {
+        let index = grid.ids.binary_search(&id).map_err(|_| format!("Unknown walkable tile id {}", id))?;
+        walkable.insert(index);
+    }
+    for &(x, y) in &points {
+        if x >= grid.cells.len() || y >= grid.cells[x].len() {
+            return Err(format!("Point ({}, {}) lies outside the grid", x, y));
+        }
+        if !grid.constrain(x, y, walkable_ids) {
+            return Err(format!("Point ({}, {}) cannot be walkable", x, y));
+        }
+    }
+    let mut connectivity = Connectivity { walkable, single, points, labels: vec![], joined: None };
+    if !connectivity.enforce(grid) {
+        return Err("The walkable cells are not connected".to_string());
+    }
+    grid.connectivity = Some(connectivity);
+    Ok(())
+}
+
+// Prints the components of walkable collapsed cells, largest first.
+// Returns true if the finished map keeps them connected as asked.
+pub fn report(grid: &Grid, walkable_ids: &[usize], points: &[(usize, usize)], single: bool) -> bool {
+    let mut walkable = Domain::empty();
+    for index in walkable_ids.iter().filter_map(|id| grid.ids.binary_search(id).ok()) {
+        walkable.insert(index);
+    }
+    // Only collapsed cells count here, open ones are not walkable yet
+    let connectivity = Connectivity { walkable, single, points: points.to_vec(), labels: vec![], joined: None };
+    let labels = connectivity.label(grid, true);
+    let components = labels.iter().flatten().flatten().max().map_or(0, |&max| max + 1);
+    let mut sizes = vec![0; components];
+    for &label in labels.iter().flatten().flatten() {
+        sizes[label] += 1;
+    }
+    sizes.sort_unstable_by(|a, b| b.cmp(a));
+
+    let total: usize = sizes.iter().sum();
+    println!("Walkable cells: {} in {} component(s)", total, sizes.len());
+    for (rank, size) in sizes.iter().take(REPORTED_COMPONENTS).enumerate() {
+        println!("  #{}: {} cells ({:.1}%)", rank + 1, size, *size as f32 * 100.0 / total.max(1) as f32);
+    }
+    if sizes.len() > REPORTED_COMPONENTS {
+        println!("  ... {} smaller component(s)", sizes.len() - REPORTED_COMPONENTS);
+    }
+
+    let mut point_labels = points.iter().map(|&(x, y)| labels.get(x).and_then(|row| row.get(y)).copied().flatten());
+    let reachable = match point_labels.next() {
+        Some(first) => first.is_some() && point_labels.all(|label| label == first),
+        None => true,
+    };
+    if !points.is_empty() {
+        println!("Points {}", if reachable { "reach each other" } else { "do NOT reach each other" });
+    }
+    reachable && (!single || sizes.len() <= 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tileset::Manifest;
+    use crate::topology::TopologyKind;
+
+    // Solves 20x20 terrain maps under the constraint, returning those that completed
+    fn solved_grids(points: &[(usize, usize)], single: bool) -> Vec<Grid> {
+        let grids: Vec<Grid> = (0..20)
+            .filter_map(|seed| {
+                let mut grid = Manifest::terrain().grid(20, 20, &TopologyKind::Square, seed).unwrap();
+                grid.quiet = true;
+                constrain(&mut grid, &DEFAULT_WALKABLE, points.to_vec(), single).unwrap();
+                grid.solve().then_some(grid)
+            })
+            .collect();
+        assert!(!grids.is_empty(), "no seed solved");
+        grids
+    }
+
+    fn walkable_labels(grid: &Grid) -> Vec<Vec<Option<usize>>> {
+        let mut walkable = Domain::empty();
+        for index in DEFAULT_WALKABLE.iter().filter_map(|id| grid.ids.binary_search(id).ok()) {
+            walkable.insert(index);
+        }
+        let connectivity = Connectivity { walkable, single: false, points: vec![], labels: vec![], joined: None };
+        connectivity.label(grid, true)
+    }
+
+    #[test]
+    fn marked_points_end_up_connected() {
+        let (a, b) = ((1, 2), (18, 17));
+        for grid in solved_grids(&[a, b], false) {
+            let labels = walkable_labels(&grid);
+            assert!(labels[a.0][a.1].is_some());
+            assert_eq!(labels[a.0][a.1], labels[b.0][b.1], "seed {}", grid.seed);
+        }
+    }
+
+    #[test]
+    fn single_area_is_one_component() {
+        for grid in solved_grids(&[], true) {
+            let labels = walkable_labels(&grid);
+            assert!(labels.iter().flatten().flatten().all(|&label| label == 0), "seed {}", grid.seed);
+        }
+    }
+}
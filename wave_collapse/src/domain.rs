@@ -0,0 +1,148 @@
+// Largest ruleset a cell domain can hold
+pub const MAX_TILES: usize = 256;
+const WORDS: usize = MAX_TILES / 64;
+
+// The tile indices a cell can still take, one bit per index into `Grid::ids`.
+// Fixed size so cells stay off the heap and copy cheaply.
+#[derive(Clone, Copy, PartialEq, Eq, Default)]
+pub struct Domain([u64; WORDS]);
+
+impl Domain {
+    pub fn empty() -> Self {
+        Self::default()
+    }
+
+    // Indices 0..count
+    pub fn full(count: usize) -> Self {
+        let mut domain = Self::empty();
+        for (word, bits) in domain.0.iter_mut().enumerate() {
+            let start = word * 64;
+            if count >= start + 64 {
+                *bits = u64::MAX;
+            } else if count > start {
+                *bits = (1 << (count - start)) - 1;
+            }
+        }
+        domain
+    }
+
+    pub fn single(index: usize) -> Self {
+        let mut domain = Self::empty();
+        domain.insert(index);
+        domain
+    }
+
+    pub fn insert(&mut self, index: usize) {
+        self.0[index / 64] |= 1 << (index % 64);
+    }
+
+    pub fn contains(&self, index: usize) -> bool {
+        self.0[index / 64] & (1 << (index % 64)) != 0
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.iter().map(|bits| bits.count_ones() as usize).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.iter().all(|&bits| bits == 0)
+    }
+
+    pub fn intersection(&self, other: &Domain) -> Domain {
+        let mut result = *self;
+        for (bits, other) in result.0.iter_mut().zip(other.0) {
+            *bits &= other;
+        }
+        result
+    }
+
+    pub fn union(&self, other: &Domain) -> Domain {
+        let mut result = *self;
+        for (bits, other) in result.0.iter_mut().zip(other.0) {
+            *bits |= other;
+        }
+        result
+    }
+
+    // Indices in `self` that are not in `other`
+    pub fn difference(&self, other: &Domain) -> Domain {
+        let mut result = *self;
+        for (bits, other) in result.0.iter_mut().zip(other.0) {
+            *bits &= !other;
+        }
+        result
+    }
+
+    // Set indices in ascending order
+    pub fn iter(&self) -> impl Iterator<Item = usize> + '_ {
+        self.0.iter().enumerate().flat_map(|(word, &bits)| {
+            let mut bits = bits;
+            std::iter::from_fn(move || {
+                if bits == 0 {
+                    return None;
+                }
+                let bit = bits.trailing_zeros() as usize;
+                bits &= bits - 1;
+                Some(word * 64 + bit)
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn full_fills_exactly_count_bits_across_words() {
+        for count in [0, 1, 63, 64, 65, 128, 255, 256] {
+            let domain = Domain::full(count);
+            assert_eq!(domain.len(), count, "full({})", count);
+            assert_eq!(domain.iter().collect::<Vec<_>>(), (0..count).collect::<Vec<_>>(), "full({})", count);
+        }
+        assert_eq!(Domain::full(64).0, [u64::MAX, 0, 0, 0]);
+        assert_eq!(Domain::full(65).0, [u64::MAX, 1, 0, 0]);
+        assert_eq!(Domain::full(256).0, [u64::MAX; WORDS]);
+    }
+
+    #[test]
+    fn iter_is_ascending_across_words() {
+        let mut domain = Domain::empty();
+        for index in [200, 3, 64, 63, 255, 0, 130] {
+            domain.insert(index);
+        }
+        assert_eq!(domain.iter().collect::<Vec<_>>(), vec![0, 3, 63, 64, 130, 200, 255]);
+    }
+
+    #[test]
+    fn difference_and_len_track_single_bits() {
+        let mut domain = Domain::full(70);
+        for index in [0, 64, 69] {
+            domain = domain.difference(&Domain::single(index));
+        }
+        // Removing what is already gone changes nothing
+        domain = domain.difference(&Domain::single(69));
+        domain = domain.difference(&Domain::single(100));
+        assert_eq!(domain.len(), 67);
+        assert!(!domain.iter().any(|index| [0, 64, 69].contains(&index)));
+        assert_eq!(domain.iter().next(), Some(1));
+        assert_eq!(domain.iter().last(), Some(68));
+
+        let single = Domain::single(255);
+        assert_eq!(single.len(), 1);
+        assert!(!single.is_empty());
+        assert!(single.difference(&Domain::single(255)) == Domain::empty());
+    }
+
+    #[test]
+    fn set_operations() {
+        let a = Domain::full(66);
+        let mut b = Domain::empty();
+        for index in [1, 65, 66, 200] {
+            b.insert(index);
+        }
+        assert_eq!(a.intersection(&b).iter().collect::<Vec<_>>(), vec![1, 65]);
+        assert_eq!(b.difference(&a).iter().collect::<Vec<_>>(), vec![66, 200]);
+        assert_eq!(a.union(&b).len(), 68);
+    }
+}
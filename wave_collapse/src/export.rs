@@ -0,0 +1,214 @@
+use serde::{Serialize, Deserialize};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use crate::Grid;
+use crate::tileset::Manifest;
+use crate::topology::TopologyKind;
+
+// One entry of the tile legend, mapping a tile id to its tileset name
+#[derive(Serialize, Deserialize)]
+pub struct LegendEntry {
+    pub id: usize,
+    pub name: String,
+}
+
+// Everything a game engine needs to rebuild the map without the pixels.
+// `tiles` and `orientations` are indexed [row][column], matching `stitch_images`.
+#[derive(Serialize, Deserialize)]
+pub struct MapExport {
+    pub width: usize,
+    pub height: usize,
+    pub seed: u64,
+    pub legend: Vec<LegendEntry>,
+    pub tiles: Vec<Vec<Option<usize>>>,
+    pub orientations: Vec<Vec<usize>>,
+    // Maps exported before hex support are square
+    #[serde(default)]
+    pub topology: TopologyKind,
+}
+
+impl MapExport {
+    pub fn from_grid(grid: &Grid) -> Self {
+        let mut ids: Vec<usize> = grid.rules.keys().copied().collect();
+        ids.sort_unstable();
+
+        Self {
+            width: grid.cells.first().map_or(0, |row| row.len()),
+            height: grid.cells.len(),
+            seed: grid.seed,
+            legend: ids.into_iter()
+                .map(|id| LegendEntry { id, name: grid.id_to_name(id as u32).to_string() })
+                .collect(),
+            tiles: grid.cells.iter()
+                .map(|row| row.iter().map(|cell| cell.value).collect())
+                .collect(),
+            orientations: grid.cells.iter()
+                .map(|row| row.iter().map(|cell| cell.orientation).collect())
+                .collect(),
+            topology: grid.topology.describe(),
+        }
+    }
+}
+
+pub fn export_json(grid: &Grid, path: &Path) -> io::Result<()> {
+    let writer = BufWriter::new(File::create(path)?);
+    serde_json::to_writer_pretty(writer, &MapExport::from_grid(grid))?;
+    println!("Exported map JSON to {}", path.display());
+    Ok(())
+}
+
+// One line per row, comma separated ids, empty field for uncollapsed cells
+pub fn export_csv(grid: &Grid, path: &Path) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    for row in &grid.cells {
+        let line: Vec<String> = row.iter()
+            .map(|cell| cell.value.map_or(String::new(), |v| v.to_string()))
+            .collect();
+        writeln!(writer, "{}", line.join(","))?;
+    }
+    println!("Exported map CSV to {}", path.display());
+    Ok(())
+}
+
+// Width and height as little endian u32, followed by one little endian u32 id
+// per cell in row-major order. Ids start at 1, so 0 marks an uncollapsed cell.
+pub fn export_raw(grid: &Grid, path: &Path) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    let width = grid.cells.first().map_or(0, |row| row.len());
+    writer.write_all(&(width as u32).to_le_bytes())?;
+    writer.write_all(&(grid.cells.len() as u32).to_le_bytes())?;
+    for cell in grid.cells.iter().flatten() {
+        writer.write_all(&(cell.value.unwrap_or(0) as u32).to_le_bytes())?;
+    }
+    println!("Exported raw ids to {}", path.display());
+    Ok(())
+}
+
+// Rebuilds an exported map with the rules, weights and images of `manifest`, which has to
+// be the tileset the map was made with
+pub fn import_json(path: &Path, manifest: &Manifest) -> io::Result<Grid> {
+    let map: MapExport = serde_json::from_reader(File::open(path)?)?;
+    if map.tiles.len() != map.height || map.tiles.iter().any(|row| row.len() != map.width) {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "Tile array does not match map dimensions"));
+    }
+    check_legend(&map.legend, manifest)?;
+    check_ids(&map.tiles, manifest)?;
+    let mut grid = manifest.grid_from_values(map.tiles, &map.topology, map.seed)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    for (row, orientations) in grid.cells.iter_mut().zip(map.orientations) {
+        for (cell, orientation) in row.iter_mut().zip(orientations) {
+            cell.orientation = orientation;
+        }
+    }
+    println!("Imported {}x{} map from {}", map.width, map.height, path.display());
+    Ok(grid)
+}
+
+// CSV maps only have ids, they are read as `manifest` tiles laid out as `topology`
+pub fn import_csv(path: &Path, manifest: &Manifest, topology: &TopologyKind, seed: u64) -> io::Result<Grid> {
+    let values = read_csv(path)?;
+    check_ids(&values, manifest)?;
+    println!("Imported {} rows from {}", values.len(), path.display());
+    manifest.grid_from_values(values, topology, seed).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+// Tile ids as written by `export_csv`, empty fields are open cells
+pub fn read_csv(path: &Path) -> io::Result<Vec<Vec<Option<usize>>>> {
+    let mut values = vec![];
+    for line in fs::read_to_string(path)?.lines().filter(|line| !line.trim().is_empty()) {
+        let row = line.split(',')
+            .map(|field| match field.trim() {
+                "" => Ok(None),
+                id => id.parse::<usize>().map(Some).map_err(|_| {
+                    io::Error::new(io::ErrorKind::InvalidData, format!("Invalid tile id: {}", id))
+                }),
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        values.push(row);
+    }
+    // Grids are rectangular, a short row would leave cells the topology expects missing
+    let width = values.first().map_or(0, |row| row.len());
+    if width == 0 || values.iter().any(|row| row.len() != width) {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "CSV map needs rows of equal length"));
+    }
+    Ok(values)
+}
+
+// A map of another tileset, e.g. a decoration layer, would otherwise come back relabelled
+fn check_legend(legend: &[LegendEntry], manifest: &Manifest) -> io::Result<()> {
+    for entry in legend {
+        match manifest.tiles.get(&entry.id) {
+            Some(name) if *name == entry.name => {}
+            found => {
+                let message = format!("Map legend has tile {} {} where tileset {} has {}, pass the manifest the map was made with to --tileset",
+                    entry.id, entry.name, manifest.name, found.map_or("nothing", String::as_str));
+                return Err(io::Error::new(io::ErrorKind::InvalidData, message));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn check_ids(values: &[Vec<Option<usize>>], manifest: &Manifest) -> io::Result<()> {
+    match values.iter().flatten().flatten().find(|id| !manifest.rules.contains_key(id)) {
+        Some(id) => Err(io::Error::new(io::ErrorKind::InvalidData, format!("Unknown tile id: {}", id))),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::path::PathBuf;
+
+    fn temp_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("wave_collapse_{}_{}", std::process::id(), name))
+    }
+
+    fn solved_grid(seed: u64) -> Grid {
+        let mut grid = Manifest::terrain().grid(6, 5, &TopologyKind::Square, seed).unwrap();
+        grid.quiet = true;
+        grid.solve();
+        grid
+    }
+
+    fn values(grid: &Grid) -> Vec<Vec<Option<usize>>> {
+        grid.cells.iter().map(|row| row.iter().map(|cell| cell.value).collect()).collect()
+    }
+
+    #[test]
+    fn json_export_imports_back_unchanged() {
+        let grid = solved_grid(7);
+        let path = temp_path("round_trip.json");
+        export_json(&grid, &path).unwrap();
+        let imported = import_json(&path, &Manifest::terrain()).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(values(&imported), values(&grid));
+        assert_eq!(imported.seed, grid.seed);
+        assert_eq!(imported.topology.size(), (6, 5));
+    }
+
+    #[test]
+    fn csv_export_imports_back_unchanged() {
+        let grid = solved_grid(8);
+        let path = temp_path("round_trip.csv");
+        export_csv(&grid, &path).unwrap();
+        let imported = import_csv(&path, &Manifest::terrain(), &TopologyKind::Square, 8).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(values(&imported), values(&grid));
+    }
+
+    #[test]
+    fn ragged_csv_is_rejected() {
+        let path = temp_path("ragged.csv");
+        fs::write(&path, "1,2,3\n1,2\n1,2,3\n").unwrap();
+        let error = import_csv(&path, &Manifest::terrain(), &TopologyKind::Square, 1).err();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(error.map(|e| e.kind()), Some(io::ErrorKind::InvalidData));
+    }
+}
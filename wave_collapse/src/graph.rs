@@ -0,0 +1,148 @@
+use serde::{Serialize, Deserialize};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::path::Path;
+use crate::Grid;
+use crate::topology::TopologyKind;
+
+// Label of edges that do not name one; it is its own opposite
+const DEFAULT_LABEL: &str = "adjacent";
+
+#[derive(Serialize, Deserialize)]
+pub struct GraphNode {
+    pub name: String,
+    // Pins the node to this tile id
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tile: Option<usize>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct GraphEdge {
+    pub from: String,
+    pub to: String,
+    #[serde(default = "default_label")]
+    pub label: String,
+}
+
+fn default_label() -> String {
+    DEFAULT_LABEL.to_string()
+}
+
+// A graph to solve, e.g.
+// `{ "nodes": [{ "name": "hall" }, { "name": "cellar", "tile": 3 }],
+//    "edges": [{ "from": "hall", "to": "cellar", "label": "down" }],
+//    "opposites": { "down": "up" } }`
+// An edge labelled L from A to B puts B in direction L of A and A in the opposite
+// direction of B. Labels without an entry in `opposites` are their own opposite.
+#[derive(Deserialize)]
+pub struct GraphFile {
+    pub nodes: Vec<GraphNode>,
+    #[serde(default)]
+    pub edges: Vec<GraphEdge>,
+    #[serde(default)]
+    pub opposites: HashMap<String, String>,
+}
+
+#[derive(Serialize)]
+struct NodeResult<'a> {
+    name: &'a str,
+    tile: Option<usize>,
+    tile_name: Option<&'a str>,
+}
+
+#[derive(Serialize)]
+struct GraphResult<'a> {
+    seed: u64,
+    nodes: Vec<NodeResult<'a>>,
+    edges: &'a [GraphEdge],
+}
+
+pub fn load_graph(path: &Path) -> io::Result<GraphFile> {
+    Ok(serde_json::from_reader(File::open(path)?)?)
+}
+
+fn label_index(labels: &mut Vec<String>, label: &str) -> usize {
+    labels.iter().position(|l| l == label).unwrap_or_else(|| {
+        labels.push(label.to_string());
+        labels.len() - 1
+    })
+}
+
+// Labels in order of first use, with the index of each one's opposite
+fn edge_labels(graph: &GraphFile) -> (Vec<String>, Vec<usize>) {
+    let mut labels: Vec<String> = vec![];
+    for edge in &graph.edges {
+        label_index(&mut labels, &edge.label);
+    }
+
+    let mut opposites: Vec<Option<usize>> = vec![];
+    let mut pairs: Vec<(&String, &String)> = graph.opposites.iter().collect();
+    pairs.sort();
+    for (label, opposite) in pairs {
+        let (label, opposite) = (label_index(&mut labels, label), label_index(&mut labels, opposite));
+        opposites.resize(labels.len(), None);
+        opposites[label] = Some(opposite);
+        opposites[opposite] = Some(label);
+    }
+    opposites.resize(labels.len(), None);
+    let opposites = opposites.into_iter().enumerate().map(|(label, opposite)| opposite.unwrap_or(label)).collect();
+    (labels, opposites)
+}
+
+// A one row grid with a cell per node, in file order, pinned nodes already collapsed
+pub fn graph_grid(graph: &GraphFile, rules: HashMap<usize, Vec<usize>>, seed: u64) -> Result<Grid, String> {
+    if graph.nodes.is_empty() {
+        return Err("Graph has no nodes".to_string());
+    }
+    let mut node_index = HashMap::new();
+    for (index, node) in graph.nodes.iter().enumerate() {
+        if node_index.insert(node.name.as_str(), index).is_some() {
+            return Err(format!("Duplicate node: {}", node.name));
+        }
+    }
+    let find = |name: &str| node_index.get(name).copied().ok_or_else(|| format!("Edge to unknown node: {}", name));
+
+    let (labels, opposites) = edge_labels(graph);
+    let mut edges = vec![vec![]; graph.nodes.len()];
+    for edge in &graph.edges {
+        let (from, to) = (find(&edge.from)?, find(&edge.to)?);
+        let label = labels.iter().position(|l| *l == edge.label).unwrap_or(0);
+        edges[from].push((label, to));
+        edges[to].push((opposites[label], from));
+    }
+
+    let mut grid = Grid::from_values(vec![vec![None; graph.nodes.len()]], rules, seed);
+    grid.set_topology(&TopologyKind::Graph { labels, opposites, edges })?;
+    for (index, node) in graph.nodes.iter().enumerate() {
+        if let Some(tile) = node.tile {
+            if !grid.rules.contains_key(&tile) {
+                return Err(format!("Unknown tile id {} pinned to {}", tile, node.name));
+            }
+            if !grid.constrain(0, index, &[tile]) {
+                return Err(format!("Pinning {} to tile {} conflicts with its neighbours", node.name, tile));
+            }
+        }
+    }
+    println!("Loaded graph with {} nodes and {} edges", graph.nodes.len(), graph.edges.len());
+    Ok(grid)
+}
+
+// Writes the tile picked for every node, together with the edges it was solved with
+pub fn export_graph_json(grid: &Grid, graph: &GraphFile, path: &Path) -> io::Result<()> {
+    let result = GraphResult {
+        seed: grid.seed,
+        nodes: graph.nodes.iter().zip(&grid.cells[0])
+            .map(|(node, cell)| NodeResult {
+                name: &node.name,
+                tile: cell.value,
+                tile_name: cell.value.map(|id| grid.id_to_name(id as u32)),
+            })
+            .collect(),
+        edges: &graph.edges,
+    };
+    let writer = BufWriter::new(File::create(path)?);
+    serde_json::to_writer_pretty(writer, &result)?;
+    println!("Exported graph solution to {}", path.display());
+    Ok(())
+}
@@ -1,108 +1,522 @@
-use ggez::{event, graphics, Context, GameResult, graphics::Image};
-use pbr::ProgressBar;
-use crate::Grid;
-use crate::ImageBuffer;
-use image::{DynamicImage, GenericImageView};
-use std::path::Path;
+use ggez::{conf, event, graphics, Context, ContextBuilder, GameResult};
+use ggez::event::{KeyCode, KeyMods, MouseButton};
+use ggez::graphics::{Color, DrawMode, DrawParam, FilterMode, Image, Mesh, Rect, Text};
+use ggez::input::{keyboard, mouse};
+use image::RgbaImage;
+use std::collections::HashMap;
+use crate::{Elimination, Grid, Step};
+use crate::render::{self, TileImages};
+
+const WINDOW_SIZE: f32 = 900.0;
+// Space below the map for the status line and the step rate slider
+const STATUS_BAR_HEIGHT: f32 = 80.0;
+const SLIDER_WIDTH: f32 = 240.0;
+const MAX_STEPS_PER_FRAME: usize = 1000;
+const MIN_ZOOM: f32 = 0.25;
+const MAX_ZOOM: f32 = 64.0;
 
 // Create GameState struct
 pub struct GameState {
     grid: Grid,
-    final_image: Option<ggez::graphics::Image>,
+    // The grid as it was before the first step, for restarts
+    initial_grid: Grid,
+    images: TileImages,
+    // CPU side render of the grid, only changed cells are redrawn into it
+    canvas: RgbaImage,
+    final_image: Option<Image>,
+    steps_per_frame: usize,
+    paused: bool,
+    finished: bool,
+    dragging_slider: bool,
+    // Sorted tile ids, number key n selects the n-th one
+    tile_ids: Vec<usize>,
+    // Tiles painted with the mouse: one pins cells, several restrict them to that subset
+    brush: Vec<usize>,
+    // Everything painted so far, replayed onto the initial grid on restarts and conflicts
+    constraints: HashMap<(usize, usize), Vec<usize>>,
+    painting: bool,
+    last_painted: Option<(usize, usize)>,
+    // Corners of the region being dragged out with the right mouse button
+    region: Option<((usize, usize), (usize, usize))>,
+    // Camera: zoom relative to fitting the whole map, and screen offset of the map origin
+    zoom: f32,
+    pan: [f32; 2],
+    panning: bool,
+    // Cell under the mouse, shown in the inspector
+    hover: Option<(usize, usize)>,
 }
 
 impl GameState {
-    pub fn new(passed_grid: Grid) -> Self {
-        GameState { 
-            grid : passed_grid,
+    pub fn new(mut passed_grid: Grid, steps_per_frame: usize, paused: bool) -> GameResult<Self> {
+        // The inspector explains why candidates are gone
+        passed_grid.track_eliminations = true;
+        let images = TileImages::load(&passed_grid)?;
+        let canvas = render::render_grid(&passed_grid, &images);
+        let mut tile_ids: Vec<usize> = passed_grid.rules.keys().copied().collect();
+        tile_ids.sort_unstable();
+        Ok(GameState {
+            initial_grid: passed_grid.clone(),
+            grid: passed_grid,
+            images,
+            canvas,
             final_image: None,
+            steps_per_frame: steps_per_frame.clamp(1, MAX_STEPS_PER_FRAME),
+            paused,
+            finished: false,
+            dragging_slider: false,
+            brush: tile_ids.first().map(|&id| vec![id]).unwrap_or_default(),
+            tile_ids,
+            constraints: HashMap::new(),
+            painting: false,
+            last_painted: None,
+            region: None,
+            zoom: 1.0,
+            pan: [0.0, 0.0],
+            panning: false,
+            hover: None,
+        })
+    }
+
+    fn advance(&mut self, steps: usize) {
+        for _ in 0..steps {
+            match self.grid.step() {
+                Step::Collapsed => {}
+                Step::Contradiction => {
+                    println!("Grid collapsing ended with a contradiction.");
+                    self.finished = true;
+                    break;
+                }
+                Step::Done => {
+                    println!("Grid collapsing completed.");
+                    self.finished = true;
+                    break;
+                }
+            }
+        }
+    }
+
+    // Back to the initial grid, optionally with a different seed
+    fn restart(&mut self, seed: Option<u64>) {
+        if let Some(seed) = seed {
+            self.initial_grid.reseed(seed);
+        }
+        println!("Restarting with seed {}", self.initial_grid.seed);
+        if !self.replay_constraints() {
+            println!("Painted constraints contradict each other");
+        }
+    }
+
+    // Rebuilds the grid from `initial_grid` with every painted constraint applied
+    fn replay_constraints(&mut self) -> bool {
+        let mut grid = self.initial_grid.clone();
+        let valid = self.constraints.iter().all(|(&(row, col), allowed)| grid.constrain(row, col, allowed));
+        grid.take_changed();
+        self.grid = grid;
+        self.canvas = render::render_grid(&self.grid, &self.images);
+        self.final_image = None;
+        self.finished = false;
+        valid
+    }
+
+    // Applies the brush to `cells` on the live grid. If the grid cannot take it, for example
+    // because a cell already collapsed to another tile, solving re-runs from the initial grid.
+    fn paint(&mut self, cells: &[(usize, usize)]) {
+        if self.brush.is_empty() || cells.is_empty() {
+            return;
         }
+        let previous: Vec<_> = cells.iter()
+            .map(|&cell| (cell, self.constraints.insert(cell, self.brush.clone())))
+            .collect();
+
+        let brush = self.brush.clone();
+        if cells.iter().all(|&(row, col)| self.grid.constrain(row, col, &brush)) {
+            self.finished = false;
+            return;
+        }
+        if !self.replay_constraints() {
+            println!("Painted constraint conflicts with earlier ones, discarding it");
+            for (cell, old) in previous {
+                match old {
+                    Some(allowed) => self.constraints.insert(cell, allowed),
+                    None => self.constraints.remove(&cell),
+                };
+            }
+            self.replay_constraints();
+        }
+    }
+
+    // Re-solves the selected cells live with a new seed, keeping painted constraints inside it
+    fn regenerate(&mut self, region: &[(usize, usize)]) {
+        let seed = rand::random();
+        println!("Regenerating {} cells with seed {}", region.len(), seed);
+        self.grid.reseed(seed);
+        let mut valid = self.grid.uncollapse(region);
+        for cell in region {
+            if let Some(allowed) = self.constraints.get(cell) {
+                valid &= self.grid.constrain(cell.0, cell.1, allowed);
+            }
+        }
+        if !valid {
+            println!("Region cannot be regenerated, its border has no compatible tiles");
+        }
+        self.finished = false;
+    }
+
+    fn brush_name(&self) -> String {
+        let names: Vec<&str> = self.brush.iter().map(|&id| self.grid.id_to_name(id as u32)).collect();
+        names.join("+")
+    }
+
+    // Scale that fits the whole map into the window above the status bar
+    fn fit_scale(&self, ctx: &Context) -> f32 {
+        let (window_width, window_height) = graphics::drawable_size(ctx);
+        let map_height = (window_height - STATUS_BAR_HEIGHT).max(1.0);
+        (window_width / self.canvas.width().max(1) as f32).min(map_height / self.canvas.height().max(1) as f32)
+    }
+
+    fn map_scale(&self, ctx: &Context) -> f32 {
+        self.fit_scale(ctx) * self.zoom
+    }
+
+    // Zooms by `factor` keeping the map point under (x, y) in place
+    fn zoom_at(&mut self, ctx: &Context, x: f32, y: f32, factor: f32) {
+        let old_scale = self.map_scale(ctx);
+        self.zoom = (self.zoom * factor).clamp(MIN_ZOOM, MAX_ZOOM);
+        let new_scale = self.map_scale(ctx);
+        self.pan[0] = x - (x - self.pan[0]) * new_scale / old_scale;
+        self.pan[1] = y - (y - self.pan[1]) * new_scale / old_scale;
+    }
+
+    fn reset_camera(&mut self) {
+        self.zoom = 1.0;
+        self.pan = [0.0, 0.0];
+    }
+
+    // Screen rectangle covered by the cells between two corners
+    fn cells_rect(&self, ctx: &Context, (row0, col0): (usize, usize), (row1, col1): (usize, usize)) -> Rect {
+        let scale = self.map_scale(ctx);
+        let (row0, row1) = (row0.min(row1), row0.max(row1));
+        let (col0, col1) = (col0.min(col1), col0.max(col1));
+        // Hex rows alternate their shift, so look at both parities
+        let corners = [row0, (row0 + 1).min(row1), row1].into_iter()
+            .flat_map(|row| [(row, col0), (row, col1)])
+            .map(|(row, col)| render::cell_origin(&self.grid, &self.images, row, col));
+        let (mut left, mut top, mut right, mut bottom) = (u32::MAX, u32::MAX, 0, 0);
+        for (x, y) in corners {
+            left = left.min(x);
+            top = top.min(y);
+            right = right.max(x + self.images.tile_width);
+            bottom = bottom.max(y + self.images.tile_height);
+        }
+        Rect::new(
+            self.pan[0] + left as f32 * scale,
+            self.pan[1] + top as f32 * scale,
+            (right - left) as f32 * scale,
+            (bottom - top) as f32 * scale,
+        )
+    }
+
+    fn screen_to_cell(&self, ctx: &Context, x: f32, y: f32) -> Option<(usize, usize)> {
+        let scale = self.map_scale(ctx);
+        render::cell_at(&self.grid, &self.images, (x - self.pan[0]) / scale, (y - self.pan[1]) / scale)
+    }
+
+    // Inspector text: coordinate, tile or candidates with weights, and eliminated values
+    fn inspect(&self, row: usize, col: usize) -> String {
+        let cell = &self.grid.cells[row][col];
+        let name = |id: usize| self.grid.id_to_name(id as u32);
+        let mut lines = vec![format!("cell ({}, {})", row, col)];
+
+        match cell.value {
+            Some(value) => {
+                lines.push(format!("tile: {}", name(value)));
+                if let Some(step) = self.grid.stats.collapse_step[row][col] {
+                    lines.push(format!("collapsed at step {}", step));
+                }
+            }
+            None if cell.domain.is_empty() => lines.push("contradiction: no candidates left".to_string()),
+            None => {
+                lines.push(format!("candidates (entropy {}):", self.grid.entropy(row, col)));
+                for value in self.grid.candidates(row, col) {
+                    lines.push(format!("  {} weight {:.2}", name(value), self.grid.weight(value)));
+                }
+            }
+        }
+
+        if !cell.eliminated.is_empty() {
+            lines.push("eliminated:".to_string());
+            for &(value, reason) in &cell.eliminated {
+                let reason = match reason {
+                    Elimination::Neighbour { x, y, value: neighbour, direction } => {
+                        let side = self.grid.topology.direction_name(self.grid.topology.opposite(direction));
+                        format!("not allowed with {} to the {} at ({}, {})", name(neighbour), side, x, y)
+                    }
+                    Elimination::Unsupported { x, y, direction } => {
+                        let side = self.grid.topology.direction_name(self.grid.topology.opposite(direction));
+                        format!("fits nothing left to the {} at ({}, {})", side, x, y)
+                    }
+                    Elimination::Constraint => "painted out".to_string(),
+                    Elimination::Unreachable => "cut off from the walkable area".to_string(),
+                };
+                lines.push(format!("  {}: {}", name(value), reason));
+            }
+        }
+        lines.join("\n")
+    }
+
+    fn paint_at(&mut self, ctx: &Context, x: f32, y: f32) {
+        if let Some(cell) = self.screen_to_cell(ctx, x, y) {
+            if self.last_painted != Some(cell) {
+                self.last_painted = Some(cell);
+                self.paint(&[cell]);
+            }
+        }
+    }
+
+    fn save_frame(&self) {
+        let path = format!("frame_{}_{}.png", self.grid.seed, self.grid.stats.steps);
+        match self.canvas.save(&path) {
+            Ok(()) => println!("Saved frame to {}", path),
+            Err(e) => println!("Failed to save frame: {:?}", e),
+        }
+    }
+
+    fn slider_bounds(ctx: &Context) -> Rect {
+        let (window_width, window_height) = graphics::drawable_size(ctx);
+        Rect::new(window_width - SLIDER_WIDTH - 20.0, window_height - STATUS_BAR_HEIGHT / 2.0 - 5.0, SLIDER_WIDTH, 10.0)
+    }
+
+    // The slider is logarithmic, from 1 to MAX_STEPS_PER_FRAME steps per frame
+    fn set_rate_from_slider(&mut self, ctx: &Context, x: f32) {
+        let bounds = Self::slider_bounds(ctx);
+        let t = ((x - bounds.x) / bounds.w).clamp(0.0, 1.0);
+        self.steps_per_frame = (MAX_STEPS_PER_FRAME as f32).powf(t).round() as usize;
+    }
+
+    fn slider_position(&self) -> f32 {
+        (self.steps_per_frame as f32).ln() / (MAX_STEPS_PER_FRAME as f32).ln()
+    }
+
+    fn status_line(&self) -> String {
+        let state = if self.finished && self.grid.has_contradiction() {
+            "contradiction"
+        } else if self.finished {
+            "done"
+        } else if self.paused {
+            "paused"
+        } else {
+            "running"
+        };
+        let contradictions: usize = self.grid.stats.contradictions.iter().flatten().sum();
+        format!(
+            "{}  seed {}  step {}  remaining {}  contradictions {}  {} steps/frame\n\
+             brush {}  constraints {}\n\
+             space: pause  s: step  +/-: speed  r: restart  n: new seed  p: save png  wheel: zoom  middle drag: pan  f: fit\n\
+             1-9: brush  shift+1-9: add to brush  left: paint  right drag: region  shift+right drag: regenerate",
+            state, self.grid.seed, self.grid.stats.steps, self.grid.remaining_cells(), contradictions, self.steps_per_frame,
+            self.brush_name(), self.constraints.len(),
+        )
     }
 }
 
 impl event::EventHandler<ggez::GameError> for GameState {
-    fn update(&mut self, ctx: &mut ggez::Context) -> ggez::GameResult<()> {
-        println!("Entering GUI::Update");
-        let total_cells = self.grid.cells.len() * self.grid.cells[0].len();
-        let mut pb = ProgressBar::new(total_cells as u64);
-        
-        let first_tile_path = format!("tileset/{}_{}.png", 1, "plains");
-
-        println!("Trying to open: {}", first_tile_path);
-    
-        let first_image: DynamicImage = image::open(&Path::new(&first_tile_path))?;
-    
-        
-        let mut image_width = first_image.width();
-        let mut image_height = first_image.height();
-        
-        // get the dimensions
-        let mut single_image_width = first_image.width();
-        let mut single_image_height = first_image.height();
-    
-        let image_width = single_image_width * self.grid.cells.len() as u32;
-        let image_height = single_image_height * self.grid.cells.len() as u32;
-                            
-        let mut final_image_buffer = ImageBuffer::new(image_width, image_height);
-            
-        while !self.grid.is_fully_collapsed() {
-            if self.grid.collapse() {
-                pb.inc();
-                for (y, row) in self.grid.cells.iter().enumerate() {
-                    for (x, cell) in row.iter().enumerate() {
-                        if let Some(value) = cell.value {
-                            let tile_name = self.grid.id_to_name(value as u32);
-                            let tile_path = format!("tileset/{}_{}.png", value, tile_name);
-                            if Path::new(&tile_path).exists() {
-                                let tile_image = image::open(&Path::new(&tile_path))?.into_rgba8();
-                                                
-                                // Update single_image dimensions
-                                single_image_width = tile_image.width();
-                                single_image_height = tile_image.height();
-                        
-                                let image_width = single_image_width * self.grid.cells.len() as u32;
-                                let image_height = single_image_height * self.grid.cells.len() as u32;
-                                                    
-                                // if final_image_buffer.width() == 0 && final_image_buffer.height() == 0 {
-                                //     final_image_buffer = ImageBuffer::new(image_width, image_height);
-                                // }
-                            
-                                let top_left_x = (x * single_image_width as usize) as u32;
-                                let top_left_y = (y * single_image_height as usize) as u32;
-                                image::imageops::overlay(&mut final_image_buffer, &tile_image, top_left_x, top_left_y);
-                            }
-                        }
-                    }
-                }
-    
-                // Convert your image::DynamicImage or image::ImageBuffer to ggez::graphics::Image
-                let image_data = final_image_buffer.clone().into_raw();
-                let ggez_image = Image::from_rgba8(ctx, image_width as u16, image_height as u16, &image_data)?;
-    
-                // Update the final_image in GameState
-                self.final_image = Some(ggez_image);
-            }
-            self.grid.propagate();
-            if self.grid.has_contradiction() {
-                pb.finish_print("Grid collapsing ended with a contradiction.");
-                event::quit(ctx);
-                return Ok(());
-            }
-        }
-        pb.finish_print("Grid collapsing completed.");
+    fn update(&mut self, _ctx: &mut Context) -> GameResult {
+        if !self.finished && !self.paused {
+            self.advance(self.steps_per_frame);
+        }
+
+        let changed = self.grid.take_changed();
+        if !changed.is_empty() {
+            for (row, col) in changed {
+                render::draw_cell(&mut self.canvas, &self.grid, &self.images, row, col);
+            }
+            // Upload again on the next draw
+            self.final_image = None;
+        }
         Ok(())
     }
 
-    fn draw(&mut self, ctx: &mut ggez::Context) -> ggez::GameResult {
+    fn draw(&mut self, ctx: &mut Context) -> GameResult {
         graphics::clear(ctx, [0.1, 0.2, 0.3, 1.0].into());
-        
+
+        if self.final_image.is_none() {
+            let mut image = Image::from_rgba8(ctx, self.canvas.width() as u16, self.canvas.height() as u16, &self.canvas)?;
+            image.set_filter(FilterMode::Nearest);
+            self.final_image = Some(image);
+        }
+        let (window_width, window_height) = graphics::drawable_size(ctx);
+        let scale = self.map_scale(ctx);
         if let Some(image) = &self.final_image {
-            let draw_params = ggez::graphics::DrawParam::default();
+            let draw_params = DrawParam::default().dest(self.pan).scale([scale, scale]);
             graphics::draw(ctx, image, draw_params)?;
         }
-    
+
+        if let Some((start, end)) = self.region {
+            let outline = Mesh::new_rectangle(ctx, DrawMode::stroke(2.0), self.cells_rect(ctx, start, end), Color::WHITE)?;
+            graphics::draw(ctx, &outline, DrawParam::default())?;
+        }
+
+        if let Some((row, col)) = self.hover {
+            let highlight = Mesh::new_rectangle(ctx, DrawMode::stroke(1.0), self.cells_rect(ctx, (row, col), (row, col)), Color::new(1.0, 1.0, 0.0, 1.0))?;
+            graphics::draw(ctx, &highlight, DrawParam::default())?;
+
+            // Inspector panel next to the cursor, kept inside the window
+            let text = Text::new(self.inspect(row, col));
+            let (text_width, text_height) = (text.width(ctx), text.height(ctx));
+            let mouse_position = mouse::position(ctx);
+            let x = (mouse_position.x + 16.0).min(window_width - text_width - 12.0).max(0.0);
+            let y = (mouse_position.y + 16.0).min(window_height - STATUS_BAR_HEIGHT - text_height - 12.0).max(0.0);
+            let panel = Mesh::new_rectangle(ctx, DrawMode::fill(), Rect::new(x, y, text_width + 12.0, text_height + 12.0), Color::new(0.0, 0.0, 0.0, 0.75))?;
+            graphics::draw(ctx, &panel, DrawParam::default())?;
+            graphics::draw(ctx, &text, DrawParam::default().dest([x + 6.0, y + 6.0]))?;
+        }
+
+        // The zoomed map may reach under the status bar
+        let status_bar = Rect::new(0.0, window_height - STATUS_BAR_HEIGHT, window_width, STATUS_BAR_HEIGHT);
+        let status_background = Mesh::new_rectangle(ctx, DrawMode::fill(), status_bar, Color::new(0.1, 0.2, 0.3, 1.0))?;
+        graphics::draw(ctx, &status_background, DrawParam::default())?;
+
+        let status = Text::new(self.status_line());
+        graphics::draw(ctx, &status, DrawParam::default().dest([10.0, window_height - STATUS_BAR_HEIGHT + 8.0]))?;
+
+        let track = Self::slider_bounds(ctx);
+        let track_mesh = Mesh::new_rectangle(ctx, DrawMode::fill(), track, Color::new(0.3, 0.3, 0.3, 1.0))?;
+        graphics::draw(ctx, &track_mesh, DrawParam::default())?;
+        let handle = Rect::new(track.x + track.w * self.slider_position() - 4.0, track.y - 5.0, 8.0, 20.0);
+        let handle_mesh = Mesh::new_rectangle(ctx, DrawMode::fill(), handle, Color::WHITE)?;
+        graphics::draw(ctx, &handle_mesh, DrawParam::default())?;
+
         graphics::present(ctx)?;
         Ok(())
     }
-    
+
+    fn key_down_event(&mut self, ctx: &mut Context, keycode: KeyCode, keymods: KeyMods, _repeat: bool) {
+        let number_keys = [
+            KeyCode::Key1, KeyCode::Key2, KeyCode::Key3, KeyCode::Key4, KeyCode::Key5,
+            KeyCode::Key6, KeyCode::Key7, KeyCode::Key8, KeyCode::Key9,
+        ];
+        if let Some(index) = number_keys.iter().position(|&key| key == keycode) {
+            if let Some(&id) = self.tile_ids.get(index) {
+                if !keymods.contains(KeyMods::SHIFT) {
+                    self.brush = vec![id];
+                } else if let Some(position) = self.brush.iter().position(|&b| b == id) {
+                    self.brush.remove(position);
+                } else {
+                    self.brush.push(id);
+                }
+            }
+            return;
+        }
+        match keycode {
+            KeyCode::Escape => event::quit(ctx),
+            KeyCode::Space => self.paused = !self.paused,
+            KeyCode::S | KeyCode::Right => {
+                // Single stepping only makes sense while paused
+                self.paused = true;
+                if !self.finished {
+                    self.advance(1);
+                }
+            }
+            KeyCode::Equals | KeyCode::Plus | KeyCode::NumpadAdd | KeyCode::Up => {
+                self.steps_per_frame = (self.steps_per_frame * 2).min(MAX_STEPS_PER_FRAME);
+            }
+            KeyCode::Minus | KeyCode::NumpadSubtract | KeyCode::Down => {
+                self.steps_per_frame = (self.steps_per_frame / 2).max(1);
+            }
+            KeyCode::R => self.restart(None),
+            KeyCode::N => self.restart(Some(rand::random())),
+            KeyCode::P => self.save_frame(),
+            KeyCode::F => self.reset_camera(),
+            _ => {}
+        }
+    }
+
+    fn mouse_button_down_event(&mut self, ctx: &mut Context, button: MouseButton, x: f32, y: f32) {
+        let track = Self::slider_bounds(ctx);
+        let grab_area = Rect::new(track.x - 8.0, track.y - 10.0, track.w + 16.0, track.h + 20.0);
+        match button {
+            MouseButton::Left if grab_area.contains([x, y]) => {
+                self.dragging_slider = true;
+                self.set_rate_from_slider(ctx, x);
+            }
+            MouseButton::Left => {
+                self.painting = true;
+                self.last_painted = None;
+                self.paint_at(ctx, x, y);
+            }
+            MouseButton::Right => {
+                self.region = self.screen_to_cell(ctx, x, y).map(|cell| (cell, cell));
+            }
+            MouseButton::Middle => self.panning = true,
+            _ => {}
+        }
+    }
+
+    fn mouse_button_up_event(&mut self, ctx: &mut Context, button: MouseButton, _x: f32, _y: f32) {
+        match button {
+            MouseButton::Left => {
+                self.dragging_slider = false;
+                self.painting = false;
+            }
+            MouseButton::Middle => self.panning = false,
+            MouseButton::Right => {
+                if let Some((start, end)) = self.region.take() {
+                    let cells = self.grid.rect_region(start, end);
+                    if keyboard::active_mods(ctx).contains(KeyMods::SHIFT) {
+                        self.regenerate(&cells);
+                    } else {
+                        self.paint(&cells);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn mouse_motion_event(&mut self, ctx: &mut Context, x: f32, y: f32, dx: f32, dy: f32) {
+        self.hover = self.screen_to_cell(ctx, x, y);
+        if self.panning {
+            self.pan[0] += dx;
+            self.pan[1] += dy;
+        }
+        if self.dragging_slider && mouse::button_pressed(ctx, MouseButton::Left) {
+            self.set_rate_from_slider(ctx, x);
+        } else if self.painting {
+            self.paint_at(ctx, x, y);
+        }
+        if let Some((start, _)) = self.region {
+            if let Some(cell) = self.screen_to_cell(ctx, x, y) {
+                self.region = Some((start, cell));
+            }
+        }
+    }
+
+    fn mouse_wheel_event(&mut self, ctx: &mut Context, _x: f32, y: f32) {
+        let position = mouse::position(ctx);
+        self.zoom_at(ctx, position.x, position.y, 1.2f32.powf(y));
+    }
+
+    fn mouse_enter_or_leave(&mut self, _ctx: &mut Context, entered: bool) {
+        if !entered {
+            self.hover = None;
+        }
+    }
+
+    fn resize_event(&mut self, ctx: &mut Context, width: f32, height: f32) {
+        // Keep one unit per pixel instead of stretching the old coordinates
+        let _ = graphics::set_screen_coordinates(ctx, Rect::new(0.0, 0.0, width, height));
+    }
+}
+
+// Opens a window that solves `grid` live, `steps_per_frame` solver steps per update.
+// Start `paused` to paint constraints before the first step.
+pub fn run(grid: Grid, steps_per_frame: usize, paused: bool) -> GameResult {
+    let (ctx, event_loop) = ContextBuilder::new("wave_collapse", "ArdenCollapse")
+        .window_setup(conf::WindowSetup::default().title("Wave Collapse"))
+        .window_mode(conf::WindowMode::default().dimensions(WINDOW_SIZE, WINDOW_SIZE + STATUS_BAR_HEIGHT).resizable(true))
+        // A viewer needs neither sound nor gamepads, and must start on machines without them
+        .modules(conf::ModuleConf { gamepad: false, audio: false })
+        .build()?;
+    let state = GameState::new(grid, steps_per_frame, paused)?;
+    event::run(ctx, event_loop, state)
 }
@@ -0,0 +1,131 @@
+use image::{ImageError, ImageResult, Rgba, RgbaImage};
+use std::fs;
+use std::path::Path;
+use crate::Grid;
+use crate::render::{self, TileImages};
+
+// Background for cells a heatmap has no value for
+const EMPTY: Rgba<u8> = Rgba([20, 20, 20, 255]);
+// How strongly a heatmap covers the tile render in overlay mode
+const OVERLAY_ALPHA: f32 = 0.6;
+
+#[derive(Clone, Copy)]
+pub enum Heatmap {
+    // Entropy when the cell was collapsed, or its current entropy if it is still open
+    Entropy,
+    // Step index at which each cell was collapsed
+    CollapseOrder,
+    // How often propagation emptied the cell
+    Contradictions,
+    // How often a collapse of the cell was undone, empty while the solver never backtracks
+    Backtracks,
+}
+
+impl Heatmap {
+    pub const ALL: [Heatmap; 4] = [Heatmap::Entropy, Heatmap::CollapseOrder, Heatmap::Contradictions, Heatmap::Backtracks];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Heatmap::Entropy => "entropy",
+            Heatmap::CollapseOrder => "collapse_order",
+            Heatmap::Contradictions => "contradictions",
+            Heatmap::Backtracks => "backtracks",
+        }
+    }
+
+    fn values(self, grid: &Grid) -> Vec<Vec<Option<f32>>> {
+        let stats = &grid.stats;
+        let count = |n: usize| if n > 0 { Some(n as f32) } else { None };
+        (0..grid.cells.len())
+            .map(|x| (0..grid.cells[x].len())
+                .map(|y| match self {
+                    Heatmap::Entropy => match stats.collapse_entropy[x][y] {
+                        Some(entropy) => Some(entropy as f32),
+                        None if grid.cells[x][y].value.is_none() => Some(grid.entropy(x, y) as f32),
+                        None => None,
+                    },
+                    Heatmap::CollapseOrder => stats.collapse_step[x][y].map(|step| step as f32),
+                    Heatmap::Contradictions => count(stats.contradictions[x][y]),
+                    Heatmap::Backtracks => count(stats.backtracks[x][y]),
+                }).collect())
+            .collect()
+    }
+}
+
+// Blue for the lowest value through green and yellow to red for the highest
+fn heat_colour(t: f32) -> Rgba<u8> {
+    let t = t.clamp(0.0, 1.0);
+    let (r, g, b) = if t < 0.5 {
+        let k = t * 2.0;
+        (0.0, k, 1.0 - k)
+    } else {
+        let k = (t - 0.5) * 2.0;
+        (k, 1.0 - k, 0.0)
+    };
+    Rgba([(r * 255.0) as u8, (g * 255.0) as u8, (b * 255.0) as u8, 255])
+}
+
+// One solid cell shape per cell, cells without a value are left fully transparent
+pub fn render_heatmap(grid: &Grid, heatmap: Heatmap, images: &TileImages) -> RgbaImage {
+    let values = heatmap.values(grid);
+    let min = values.iter().flatten().flatten().copied().fold(f32::INFINITY, f32::min);
+    let max = values.iter().flatten().flatten().copied().fold(f32::NEG_INFINITY, f32::max);
+    let range = if max > min { max - min } else { 1.0 };
+
+    let (width, height) = render::canvas_size(grid, images);
+    let mut image = RgbaImage::new(width, height);
+    for (row, row_values) in values.iter().enumerate() {
+        for (col, value) in row_values.iter().enumerate() {
+            if let Some(value) = value {
+                let colour = heat_colour((value - min) / range);
+                let (left, top) = render::cell_origin(grid, images, row, col);
+                for y in 0..images.tile_height {
+                    for x in (0..images.tile_width).filter(|&x| render::covers(grid, images, x, y)) {
+                        image.put_pixel(left + x, top + y, colour);
+                    }
+                }
+            }
+        }
+    }
+    image
+}
+
+// Mixes the opaque pixels of `heat` into `base`
+fn blend(base: &mut RgbaImage, heat: &RgbaImage, alpha: f32) {
+    for (base_pixel, heat_pixel) in base.pixels_mut().zip(heat.pixels()) {
+        if heat_pixel[3] == 0 {
+            continue;
+        }
+        for channel in 0..3 {
+            let mixed = base_pixel[channel] as f32 * (1.0 - alpha) + heat_pixel[channel] as f32 * alpha;
+            base_pixel[channel] = mixed.round() as u8;
+        }
+    }
+}
+
+// Writes `<name>.png` for every heatmap into `dir`, either on their own or on top of the tile render
+pub fn write_heatmaps(grid: &Grid, dir: &Path, overlay: bool) -> ImageResult<()> {
+    fs::create_dir_all(dir).map_err(ImageError::IoError)?;
+    let images = TileImages::load(grid)?;
+    let tile_render = if overlay { Some(render::render_grid(grid, &images)) } else { None };
+
+    for heatmap in Heatmap::ALL {
+        let heat = render_heatmap(grid, heatmap, &images);
+        let output = match &tile_render {
+            Some(tiles) => {
+                let mut output = tiles.clone();
+                blend(&mut output, &heat, OVERLAY_ALPHA);
+                output
+            }
+            None => {
+                let mut output = RgbaImage::from_pixel(heat.width(), heat.height(), EMPTY);
+                blend(&mut output, &heat, 1.0);
+                output
+            }
+        };
+        let path = dir.join(format!("{}.png", heatmap.name()));
+        output.save(&path)?;
+        println!("Saved {} heatmap to {}", heatmap.name(), path.display());
+    }
+    Ok(())
+}
@@ -0,0 +1,172 @@
+use image::ImageResult;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use std::collections::HashMap;
+use std::path::Path;
+use crate::tileset::Manifest;
+
+// Height each terrain tile is most likely at, 0 is the deepest sea and 1 the highest peak
+const TILE_HEIGHTS: [(usize, f32); 6] = [
+    (6, 0.15),
+    (5, 0.35),
+    (4, 0.45),
+    (1, 0.55),
+    (2, 0.65),
+    (3, 0.85),
+];
+// How far from its height a tile still gets picked regularly
+const SPREAD: f32 = 0.15;
+// Tiles far from their height keep a little weight, adjacency may still need them
+const MIN_FACTOR: f32 = 0.001;
+// Noise layers summed up, each one twice as fine and half as strong as the one before
+const OCTAVES: u32 = 4;
+
+// A height per cell, indexed like `Grid::cells`, that biases which tile a cell collapses to
+pub struct Heights {
+    values: Vec<Vec<f32>>,
+    // Height each tile id prefers, `TILE_HEIGHTS` for the built-in terrain
+    preferred: HashMap<usize, f32>,
+}
+
+impl Heights {
+    // Greyscale image stretched over the grid, black is low and white is high
+    pub fn from_image(path: &Path, rows: usize, cols: usize) -> ImageResult<Self> {
+        let image = image::open(path)?.into_luma8();
+        let values = (0..rows)
+            .map(|x| (0..cols)
+                .map(|y| {
+                    let pixel = image.get_pixel((y * image.width() as usize / cols) as u32, (x * image.height() as usize / rows) as u32);
+                    pixel[0] as f32 / 255.0
+                })
+                .collect())
+            .collect();
+        Ok(Self { values, preferred: TILE_HEIGHTS.into_iter().collect() })
+    }
+
+    // Fractal Perlin noise from `seed`, with features about `scale` cells across
+    pub fn from_noise(seed: u64, rows: usize, cols: usize, scale: f32) -> Self {
+        let perlin = Perlin::new(seed);
+        let mut values: Vec<Vec<f32>> = (0..rows)
+            .map(|x| (0..cols)
+                .map(|y| {
+                    (0..OCTAVES)
+                        .map(|octave| {
+                            let frequency = 2f32.powi(octave as i32) / scale.max(1.0);
+                            perlin.noise(x as f32 * frequency, y as f32 * frequency) / 2f32.powi(octave as i32)
+                        })
+                        .sum()
+                })
+                .collect())
+            .collect();
+
+        // Summed octaves bunch up around the middle, spread them evenly over 0..1 by rank
+        // so every tile height gets about the same share of the map
+        let mut sorted: Vec<f32> = values.iter().flatten().copied().collect();
+        sorted.sort_unstable_by(f32::total_cmp);
+        let last = sorted.len().saturating_sub(1).max(1) as f32;
+        for value in values.iter_mut().flatten() {
+            *value = sorted.partition_point(|&other| other < *value) as f32 / last;
+        }
+        Self { values, preferred: TILE_HEIGHTS.into_iter().collect() }
+    }
+
+    // Heights for the tiles of a manifest instead of the built-in terrain: each tile prefers
+    // the mean height of the terrain it may be placed on, tiles without terrain are not biased
+    pub fn for_manifest(mut self, manifest: &Manifest) -> Self {
+        self.preferred = manifest.terrain.iter()
+            .filter_map(|(&id, terrain)| {
+                let heights: Vec<f32> = terrain.iter()
+                    .filter_map(|terrain_id| TILE_HEIGHTS.iter().find(|&&(tile, _)| tile == *terrain_id))
+                    .map(|&(_, height)| height)
+                    .collect();
+                (!heights.is_empty()).then(|| (id, heights.iter().sum::<f32>() / heights.len() as f32))
+            })
+            .collect();
+        self
+    }
+
+    // How much more likely tile `id` is at (x, y); tiles without a height are not biased
+    pub fn factor(&self, x: usize, y: usize, id: usize) -> f32 {
+        let height = self.values.get(x).and_then(|row| row.get(y));
+        match (height, self.preferred.get(&id)) {
+            (Some(height), Some(&preferred)) => (-((height - preferred) / SPREAD).powi(2)).exp().max(MIN_FACTOR),
+            _ => 1.0,
+        }
+    }
+}
+
+// Classic gradient noise with a permutation table shuffled by the seed
+struct Perlin {
+    permutation: Vec<usize>,
+}
+
+impl Perlin {
+    fn new(seed: u64) -> Self {
+        let mut permutation: Vec<usize> = (0..256).collect();
+        permutation.shuffle(&mut StdRng::seed_from_u64(seed));
+        permutation.extend_from_within(..);
+        Self { permutation }
+    }
+
+    fn gradient(hash: usize, x: f32, y: f32) -> f32 {
+        match hash & 3 {
+            0 => x + y,
+            1 => -x + y,
+            2 => x - y,
+            _ => -x - y,
+        }
+    }
+
+    fn fade(t: f32) -> f32 {
+        t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+    }
+
+    fn lerp(t: f32, a: f32, b: f32) -> f32 {
+        a + t * (b - a)
+    }
+
+    // Roughly -1..1, smooth between integer coordinates
+    fn noise(&self, x: f32, y: f32) -> f32 {
+        let p = &self.permutation;
+        let (xi, yi) = ((x.floor() as i64 & 255) as usize, (y.floor() as i64 & 255) as usize);
+        let (xf, yf) = (x - x.floor(), y - y.floor());
+        let (u, v) = (Self::fade(xf), Self::fade(yf));
+        let (a, b) = (p[xi] + yi, p[xi + 1] + yi);
+        Self::lerp(v,
+            Self::lerp(u, Self::gradient(p[a], xf, yf), Self::gradient(p[b], xf - 1.0, yf)),
+            Self::lerp(u, Self::gradient(p[a + 1], xf, yf - 1.0), Self::gradient(p[b + 1], xf - 1.0, yf - 1.0)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+    use std::path::PathBuf;
+
+    #[test]
+    fn manifest_tiles_prefer_the_height_of_their_terrain() {
+        let manifest = Manifest {
+            name: "decorations".to_string(),
+            tileset: PathBuf::from("decorations"),
+            tiles: [(1, "none"), (2, "boat"), (3, "trees")].into_iter().map(|(id, name)| (id, name.to_string())).collect(),
+            rules: BTreeMap::new(),
+            edge_rules: None,
+            weights: BTreeMap::new(),
+            // Boats on ocean, trees on plains or forest
+            terrain: [(2, vec![6]), (3, vec![1, 2])].into_iter().collect(),
+        };
+        let heights = Heights::from_noise(1, 4, 4, 2.0).for_manifest(&manifest);
+
+        assert_eq!(heights.preferred.get(&2), Some(&0.15));
+        assert!((heights.preferred[&3] - 0.6).abs() < 1e-6);
+        // Ids of the built-in terrain mean nothing here, and tiles without terrain go anywhere
+        for x in 0..4 {
+            for y in 0..4 {
+                assert_eq!(heights.factor(x, y, 1), 1.0);
+                assert_eq!(heights.factor(x, y, 6), 1.0);
+            }
+        }
+    }
+}
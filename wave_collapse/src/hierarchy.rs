@@ -0,0 +1,128 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use crate::Grid;
+use crate::domain::Domain;
+use crate::tileset::Tileset;
+use crate::topology::TopologyKind;
+
+// Seeds tried for the biome map before giving up
+const MAX_ATTEMPTS: u64 = 10;
+
+// Biomes of the coarse grid: id, name, relative weight and the built-in terrain tiles each one
+// allows in the fine grid. Biomes that may touch share a tile, so their borders can be solved.
+const BIOMES: [(usize, &str, f32, &[usize]); 4] = [
+    (1, "sea", 3.0, &[5, 6]),
+    (2, "coast", 1.0, &[4, 5, 6]),
+    (3, "lowland", 3.0, &[1, 2, 3, 4]),
+    (4, "highland", 1.0, &[2, 3]),
+];
+
+// Which biomes may sit next to each other, from the sea up to the mountains
+fn biome_rules() -> HashMap<usize, Vec<usize>> {
+    let mut rules = HashMap::new();
+    rules.insert(1, vec![1, 2]);
+    rules.insert(2, vec![1, 2, 3]);
+    rules.insert(3, vec![2, 3, 4]);
+    rules.insert(4, vec![3, 4]);
+    rules
+}
+
+fn biome_tileset() -> Tileset {
+    Tileset {
+        dir: PathBuf::from("tileset"),
+        names: BIOMES.iter().map(|&(id, name, _, _)| (id, name.to_string())).collect(),
+    }
+}
+
+// Solves a biome map with one cell per `scale` x `scale` block of `grid`
+fn solve_biomes(grid: &Grid, scale: usize, wrap: bool) -> Result<Grid, String> {
+    let (rows, cols) = grid.topology.size();
+    for attempt in 0..MAX_ATTEMPTS {
+        let mut biomes = Grid::from_values(vec![vec![None; cols.div_ceil(scale)]; rows.div_ceil(scale)], biome_rules(), grid.seed.wrapping_add(attempt));
+        biomes.set_tileset(biome_tileset());
+        biomes.set_weights(BIOMES.iter().map(|&(id, _, weight, _)| (id, weight)).collect());
+        if wrap {
+            biomes.set_topology(&TopologyKind::Wrap)?;
+        }
+        biomes.quiet = true;
+        if biomes.solve() {
+            return Ok(biomes);
+        }
+    }
+    Err("Biome map ran into a contradiction with every seed".to_string())
+}
+
+// Coarse-to-fine generation: solves a low resolution biome map first, then limits every
+// cell of `grid` to the terrain tiles of the biome covering it, so the fine solve follows
+// the large-scale shapes of continents and mountain ranges
+pub fn constrain_to_biomes(grid: &mut Grid, scale: usize) -> Result<(), String> {
+    let wrap = match grid.topology.describe() {
+        TopologyKind::Square | TopologyKind::Hex => false,
+        TopologyKind::Wrap => true,
+        _ => return Err(format!("Biome maps only cover flat grids, not {} grids", grid.topology.name())),
+    };
+    if scale < 2 {
+        return Err("Biome cells need to cover at least 2x2 cells".to_string());
+    }
+    let biomes = solve_biomes(grid, scale, wrap)?;
+    println!("Solved {}x{} biome map, one biome per {}x{} cells", biomes.cells.len(), biomes.cells[0].len(), scale, scale);
+
+    let mut counts: HashMap<usize, usize> = HashMap::new();
+    let mut domains: Vec<Vec<Domain>> = grid.cells.iter().map(|row| row.iter().map(|cell| cell.domain).collect()).collect();
+    for (x, row) in domains.iter_mut().enumerate() {
+        for (y, domain) in row.iter_mut().enumerate() {
+            let Some(biome) = biomes.cells[x / scale][y / scale].value else {
+                continue;
+            };
+            *counts.entry(biome).or_default() += 1;
+            let &(_, _, _, allowed) = BIOMES.iter().find(|&&(id, ..)| id == biome).expect("Biome map only holds known biomes");
+            let mut mask = Domain::empty();
+            for index in allowed.iter().filter_map(|id| grid.ids.binary_search(id).ok()) {
+                mask.insert(index);
+            }
+            *domain = domain.intersection(&mask);
+        }
+    }
+    support_borders(grid, &mut domains);
+
+    for (x, row) in domains.iter().enumerate() {
+        for (y, domain) in row.iter().enumerate() {
+            let allowed: Vec<usize> = domain.iter().map(|index| grid.ids[index]).collect();
+            if !grid.constrain(x, y, &allowed) {
+                return Err(format!("Cell ({}, {}) fits no tile of its biome", x, y));
+            }
+        }
+    }
+    for (id, name, _, _) in BIOMES {
+        println!("  {}: {} cells", name, counts.get(&id).copied().unwrap_or(0));
+    }
+    Ok(())
+}
+
+// Drops tiles that no tile allowed next to them can sit beside, until nothing changes.
+// The solver only narrows cells next to collapsed ones, so without this it would happily
+// put ocean on a coast cell bordering lowland and only notice once it gets there.
+fn support_borders(grid: &Grid, domains: &mut [Vec<Domain>]) {
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for x in 0..domains.len() {
+            for y in 0..domains[x].len() {
+                let neighbours = grid.topology.neighbours(x, y);
+                let supported: Vec<usize> = domains[x][y].iter()
+                    .filter(|&index| neighbours.iter().all(|&(direction, (nx, ny))| {
+                        !grid.compatible[direction][index].intersection(&domains[nx][ny]).is_empty()
+                    }))
+                    .collect();
+                if supported.len() < domains[x][y].len() {
+                    let mut domain = Domain::empty();
+                    for index in supported {
+                        domain.insert(index);
+                    }
+                    domains[x][y] = domain;
+                    changed = true;
+                }
+            }
+        }
+    }
+}
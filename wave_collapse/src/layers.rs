@@ -0,0 +1,53 @@
+use image::{imageops, ImageError, ImageResult, RgbaImage};
+use image::error::{ParameterError, ParameterErrorKind};
+use crate::{render, Grid};
+use crate::render::TileImages;
+use crate::tileset::Manifest;
+
+// A tile layer stacked on a solved terrain grid, e.g. roads, trees and buildings
+pub struct Layer {
+    pub name: String,
+    pub grid: Grid,
+}
+
+// Solves a grid of `manifest` tiles over `terrain`, with the same shape and topology.
+// Each cell only gets the layer tiles the manifest allows on the terrain tile below it,
+// adjacency within the layer follows the manifest's own rules.
+pub fn generate(terrain: &Grid, manifest: &Manifest, seed: u64) -> Result<Layer, String> {
+    let (rows, cols) = terrain.topology.size();
+    let mut grid = manifest.grid(rows, cols, &terrain.topology.describe(), seed)?;
+    println!("Generating layer {} with seed {}", manifest.name, seed);
+
+    for (x, row) in terrain.cells.iter().enumerate() {
+        for (y, cell) in row.iter().enumerate() {
+            let Some(below) = cell.value else {
+                continue;
+            };
+            let allowed: Vec<usize> = manifest.rules.keys().copied()
+                .filter(|id| manifest.terrain.get(id).is_none_or(|on| on.contains(&below)))
+                .collect();
+            if !grid.constrain(x, y, &allowed) {
+                return Err(format!("No {} tile fits on {} at ({}, {})", manifest.name, terrain.id_to_name(below as u32), x, y));
+            }
+        }
+    }
+    grid.run();
+    Ok(Layer { name: manifest.name.clone(), grid })
+}
+
+// The terrain with every layer drawn over it in order; transparent layer pixels let
+// what is below show through, so layer tiles need the same size as the terrain tiles
+pub fn render_layers(terrain: &Grid, layers: &[Layer]) -> ImageResult<RgbaImage> {
+    let images = TileImages::load(terrain)?;
+    let mut canvas = render::render_grid(terrain, &images);
+    for layer in layers {
+        let layer_images = TileImages::load(&layer.grid)?;
+        if (layer_images.tile_width, layer_images.tile_height) != (images.tile_width, images.tile_height) {
+            println!("Layer {} has {}x{} tiles, the terrain {}x{}", layer.name, layer_images.tile_width,
+                layer_images.tile_height, images.tile_width, images.tile_height);
+            return Err(ImageError::Parameter(ParameterError::from_kind(ParameterErrorKind::DimensionMismatch)));
+        }
+        imageops::overlay(&mut canvas, &render::render_overlay(&layer.grid, &layer_images), 0, 0);
+    }
+    Ok(canvas)
+}
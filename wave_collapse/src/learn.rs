@@ -0,0 +1,135 @@
+use image::{GenericImageView, RgbaImage};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+use crate::export;
+use crate::tileset::{Manifest, Tileset};
+use crate::topology::{EdgeRules, TopologyKind};
+
+// Tile ids of an example map: a CSV like `--csv` writes, or an image put together from
+// the tiles of `tileset`, e.g. a `final_image.png` that was painted over by hand
+fn read_example(path: &Path, tileset: &Tileset, kind: &TopologyKind) -> Result<Vec<Vec<Option<usize>>>, String> {
+    if path.extension().is_some_and(|ext| ext == "csv") {
+        return export::read_csv(path).map_err(|e| e.to_string());
+    }
+    if !matches!(kind, TopologyKind::Square | TopologyKind::Wrap) {
+        return Err("Only square maps can be read from an image, use a CSV for other grids".to_string());
+    }
+    read_tile_image(path, tileset)
+}
+
+// Cuts the image into tile sized blocks and takes the closest tile image for each
+fn read_tile_image(path: &Path, tileset: &Tileset) -> Result<Vec<Vec<Option<usize>>>, String> {
+    let image = image::open(path).map_err(|e| format!("{}: {:?}", path.display(), e))?.into_rgba8();
+    let mut tiles: Vec<(usize, RgbaImage)> = vec![];
+    for &id in tileset.names.keys().collect::<BTreeSet<_>>() {
+        let tile_path = tileset.image_path(id);
+        let tile = image::open(&tile_path).map_err(|e| format!("{}: {:?}", tile_path.display(), e))?.into_rgba8();
+        tiles.push((id, tile));
+    }
+    let Some((_, first)) = tiles.first() else {
+        return Err("The tileset has no tiles to look for".to_string());
+    };
+    let (width, height) = first.dimensions();
+    if tiles.iter().any(|(_, tile)| tile.dimensions() != (width, height)) {
+        return Err("Tiles of different sizes cannot be told apart in an image".to_string());
+    }
+    if image.width() % width != 0 || image.height() % height != 0 {
+        return Err(format!("A {}x{} image does not split into {}x{} tiles", image.width(), image.height(), width, height));
+    }
+
+    let mut inexact = 0;
+    let values = (0..image.height() / height)
+        .map(|row| (0..image.width() / width)
+            .map(|col| {
+                let block = image.view(col * width, row * height, width, height);
+                let (id, difference) = tiles.iter()
+                    .map(|(id, tile)| (*id, difference(&block, tile)))
+                    .min_by_key(|&(_, difference)| difference)
+                    .expect("The tileset has tiles");
+                if difference > 0 {
+                    inexact += 1;
+                }
+                Some(id)
+            })
+            .collect())
+        .collect();
+    if inexact > 0 {
+        println!("{} cells matched no tile exactly and got the closest one", inexact);
+    }
+    Ok(values)
+}
+
+// Summed channel differences between a block of the example image and a tile image
+fn difference(block: &impl GenericImageView<Pixel = image::Rgba<u8>>, tile: &RgbaImage) -> u64 {
+    block.pixels()
+        .map(|(x, y, pixel)| pixel.0.iter().zip(tile.get_pixel(x, y).0)
+            .map(|(&a, b)| (a as i64 - b as i64).unsigned_abs())
+            .sum::<u64>())
+        .sum()
+}
+
+// Derives rules and weights from an example map laid out as `kind`: tiles seen next to
+// each other may touch, on the sides they were seen on, and each tile weighs as much as
+// it is used. Tiles that never appear in the example are left out of the manifest.
+pub fn learn(path: &Path, tileset: &Tileset, kind: &TopologyKind) -> Result<Manifest, String> {
+    let values = read_example(path, tileset, kind)?;
+    let rows = values.len();
+    let cols = values.first().map_or(0, |row| row.len());
+    if rows == 0 || cols == 0 || values.iter().any(|row| row.len() != cols) {
+        return Err("The example map needs rows of equal length".to_string());
+    }
+    let topology = kind.build(rows, cols)?;
+    let directions = topology.direction_count();
+
+    let mut counts: BTreeMap<usize, usize> = BTreeMap::new();
+    let mut edges: BTreeMap<usize, Vec<BTreeSet<usize>>> = BTreeMap::new();
+    for (x, row) in values.iter().enumerate() {
+        for (y, &value) in row.iter().enumerate() {
+            let Some(id) = value else {
+                continue;
+            };
+            if !tileset.names.contains_key(&id) {
+                return Err(format!("Tile {} at ({}, {}) is not in the tileset", id, x, y));
+            }
+            *counts.entry(id).or_default() += 1;
+            let sides = edges.entry(id).or_insert_with(|| vec![BTreeSet::new(); directions]);
+            // Every pair is seen from both ends, so the rules come out symmetric
+            for (direction, (nx, ny)) in topology.neighbours(x, y) {
+                if let Some(neighbour) = values[nx][ny] {
+                    sides[direction].insert(neighbour);
+                }
+            }
+        }
+    }
+    let total: usize = counts.values().sum();
+    println!("Learned from {} cells of {} tiles in {}", total, counts.len(), path.display());
+
+    let rules: BTreeMap<usize, Vec<usize>> = edges.iter()
+        .map(|(&id, sides)| (id, sides.iter().flatten().copied().collect::<BTreeSet<_>>().into_iter().collect()))
+        .collect();
+    let edge_rules: EdgeRules = edges.iter()
+        .map(|(&id, sides)| (id, sides.iter().map(|side| side.iter().copied().collect()).collect()))
+        .collect();
+    // Only worth keeping if some tile fits fewer tiles on one side than on all of them
+    let directional = edge_rules.iter().any(|(id, sides)| sides.iter().any(|side: &Vec<usize>| side.len() < rules[id].len()));
+
+    for (&id, &count) in &counts {
+        println!("  {} {}: {} cells, next to {:?}", id, tileset.names[&id], count, rules[&id]);
+    }
+    for id in tileset.names.keys().collect::<BTreeSet<_>>().into_iter().filter(|id| !counts.contains_key(id)) {
+        println!("  {} {}: not in the example, left out", id, tileset.names[id]);
+    }
+    if directional {
+        println!("Some tiles only fit on certain sides, keeping per-side edge rules");
+    }
+
+    Ok(Manifest {
+        name: path.file_stem().map_or_else(|| "learned".to_string(), |stem| stem.to_string_lossy().into_owned()),
+        tileset: tileset.dir.clone(),
+        tiles: counts.keys().map(|&id| (id, tileset.names[&id].clone())).collect(),
+        rules,
+        edge_rules: directional.then_some(edge_rules),
+        weights: counts.iter().map(|(&id, &count)| (id, count as f32)).collect(),
+        terrain: BTreeMap::new(),
+    })
+}
@@ -1,18 +1,47 @@
-use image::{ImageBuffer, GenericImageView, DynamicImage, ImageError};
-use std::collections::HashMap;
-use rand::Rng;
-use glob;
-use rand;
+use image::ImageError;
+use std::collections::{BinaryHeap, HashMap};
+use std::cmp::Reverse;
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
 use std::fmt;
-use ggez::{ContextBuilder, event};
 use std::env;
 use std::path::Path;
 use pbr::ProgressBar;
 use std::process;
+use std::time::Duration;
 
 //use gui::gui::{Gui, Flags};
 
 mod gui;
+mod export;
+mod tiled;
+mod tiled_map;
+mod render;
+mod animation;
+mod heatmap;
+mod domain;
+mod batch;
+mod chunks;
+mod topology;
+mod voxel;
+mod graph;
+mod connectivity;
+mod tileset;
+mod layers;
+mod hierarchy;
+mod heightmap;
+mod learn;
+mod validate;
+mod font;
+mod ruleset;
+mod paths;
+
+use domain::Domain;
+use std::sync::Arc;
+use connectivity::Connectivity;
+use heightmap::Heights;
+use tileset::Tileset;
+use topology::{EdgeRules, Topology, TopologyKind};
 
 
 
@@ -22,32 +51,82 @@ impl fmt::Display for Grid {
             for cell in row {
                 write!(f, "{:?} ", cell.value)?;
             }
-            write!(f, "\n")?;
+            writeln!(f)?;
         }
         Ok(())
     }
 }
 
+// Why a value was removed from a cell's possible values
+#[derive(Clone, Copy)]
+enum Elimination {
+    // Not allowed in `direction` of the collapsed neighbour at (x, y)
+    Neighbour { x: usize, y: usize, value: usize, direction: usize },
+    // No option left at the open neighbour at (x, y) fits beside it in `direction`
+    Unsupported { x: usize, y: usize, direction: usize },
+    // Excluded by a painted constraint
+    Constraint,
+    // Walkable, but cut off from the walkable cells it has to connect to
+    Unreachable,
+}
+
+// Solver state of one grid position
 #[derive(Clone)]
-struct Tile {
-    id: usize,
-    name: String,
+struct Cell {
     value: Option<usize>,
-    possible_values: Vec<usize>,
+    // Rotation/reflection of the collapsed tile, 0 = as drawn. The solver never turns
+    // tiles, this only carries what an imported JSON map says through to its export
+    orientation: usize,
+    // Indices into `Grid::ids` still possible here
+    domain: Domain,
+    // Sum of the weights in `domain`, kept in step with it by `Grid::narrow`
+    weight_sum: f32,
+    // Only filled when the grid tracks eliminations, see `Grid::track_eliminations`
+    eliminated: Vec<(usize, Elimination)>,
 }
 
-
-impl Tile {
-    fn new(id: usize, name: String, possible_values: Vec<usize>) -> Self {
+impl Cell {
+    fn new(value: Option<usize>, domain: Domain, weights: &[f32]) -> Self {
         Self {
-            id,
-            name,
-            value: None,
-            possible_values,
+            value,
+            orientation: 0,
+            domain,
+            weight_sum: domain_weight(&domain, weights),
+            eliminated: vec![],
         }
     }
 }
 
+fn domain_weight(domain: &Domain, weights: &[f32]) -> f32 {
+    domain.iter().map(|index| weights[index]).sum()
+}
+
+fn check_tiles(tiles: &[usize], rules: &HashMap<usize, Vec<usize>>) -> Result<(), &'static str> {
+    if tiles.is_empty() {
+        return Err("No tiles provided");
+    }
+    if rules.len() > domain::MAX_TILES {
+        return Err("Too many tiles in the ruleset");
+    }
+    Ok(())
+}
+
+// `Grid::compatible` for a ruleset that is the same across every edge
+fn undirected_compatibility(ids: &[usize], rules: &HashMap<usize, Vec<usize>>, directions: usize) -> Vec<Vec<Domain>> {
+    let allowed: Vec<Domain> = ids.iter()
+        .map(|id| {
+            let mut domain = Domain::empty();
+            for neighbour in &rules[id] {
+                if let Ok(index) = ids.binary_search(neighbour) {
+                    domain.insert(index);
+                }
+            }
+            domain
+        })
+        .collect();
+    vec![allowed; directions]
+}
+
 
 
 fn get_ruleset() -> HashMap<usize, Vec<usize>> {
@@ -61,47 +140,29 @@ fn get_ruleset() -> HashMap<usize, Vec<usize>> {
     rules
 }
 
-fn stitch_images(grid: &Grid) -> Result<(), ImageError> {
-    // Load the first image to get the dimensions
-    let first_tile = &grid.cells[0][0];
-    let first_tile_name = grid.id_to_name(first_tile.value.unwrap() as u32);
-
-    let first_tile_path = format!("tileset/{}_{}.png", first_tile.value.unwrap(), first_tile_name);
-
-    println!("Trying to open: {}", first_tile_path);
-
-    let first_image: DynamicImage = image::open(&Path::new(&first_tile_path))?;
-
-    // get the dimensions
-    let single_image_width = first_image.width();
-    let single_image_height = first_image.height();
+// Relative likelihood of each tile being picked when a cell collapses
+fn get_weights() -> HashMap<usize, f32> {
+    let mut weights = HashMap::new();
+    weights.insert(1, 1.0);
+    weights.insert(2, 1.0);
+    weights.insert(3, 1.0);
+    weights.insert(4, 1.0);
+    weights.insert(5, 1.0);
+    weights.insert(6, 1.0);
+    weights
+}
 
-    // create an empty image with the size of all combined images
-    let image_width = single_image_width * grid.cells.len() as u32;
-    let image_height = single_image_height * grid.cells.len() as u32;
-    let mut final_image = ImageBuffer::new(image_width, image_height);
+fn stitch_images(grid: &Grid, layers: &[layers::Layer]) -> Result<(), ImageError> {
+    // Partial and contradicted grids render too, see `render::draw_cell`
+    let final_image = layers::render_layers(grid, layers)?;
 
-    for (y, row) in grid.cells.iter().enumerate() {
-        for (x, cell) in row.iter().enumerate() {
-            if let Some(value) = cell.value {
-                let tile_name = grid.id_to_name(value as u32);
-                let tile_path = format!("tileset/{}_{}.png", value, tile_name);
-                let tile_image = image::open(&Path::new(&tile_path))?.into_rgba8();
-                
-                // paste the image at the correct position
-                let top_left_x = (x * single_image_width as usize) as u32;
-                let top_left_y = (y * single_image_height as usize) as u32;
-                image::imageops::overlay(&mut final_image, &tile_image, top_left_x, top_left_y);
-            }
-        }
-    }
-    
     println!("Saving final image");
     // save the final image
     final_image.save("final_image.png")
 }
 
-fn load_tiles(possible_values: Vec<usize>, current_dir: &Path) -> Vec<Tile> {
+// Ids of the tile images found in the tileset directory
+fn load_tiles(current_dir: &Path) -> Vec<usize> {
     //println!("Current Directory is {}", current_dir as str); 
 
     let tileset_path = current_dir.join("tileset/*.png");
@@ -128,8 +189,8 @@ fn load_tiles(possible_values: Vec<usize>, current_dir: &Path) -> Vec<Tile> {
                     }
                 };
 
-                let name = parts[1].to_string();
-                tiles.push(Tile::new(id, name.clone(), possible_values.clone()));
+                let name = parts[1];
+                tiles.push(id);
 
                 println!("Successfully loaded tile with id: {}, name: {}", id, name); 
             }
@@ -141,125 +202,477 @@ fn load_tiles(possible_values: Vec<usize>, current_dir: &Path) -> Vec<Tile> {
 }
 
 
+// Per-cell solver history, indexed like `Grid::cells`, used by the debug heatmaps
+#[derive(Clone)]
+struct SolveStats {
+    steps: usize,
+    collapse_step: Vec<Vec<Option<usize>>>,
+    collapse_entropy: Vec<Vec<Option<usize>>>,
+    contradictions: Vec<Vec<usize>>,
+    backtracks: Vec<Vec<usize>>, // Stays zero, the solver stops at the first contradiction
+}
+
+impl SolveStats {
+    fn new(rows: usize, cols: usize) -> Self {
+        Self {
+            steps: 0,
+            collapse_step: vec![vec![None; cols]; rows],
+            collapse_entropy: vec![vec![None; cols]; rows],
+            contradictions: vec![vec![0; cols]; rows],
+            backtracks: vec![vec![0; cols]; rows],
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct Grid {
-    cells: Vec<Vec<Tile>>, // A 2D grid of tiles
+    cells: Vec<Vec<Cell>>, // A 2D grid of tiles
     rules: HashMap<usize, Vec<usize>>,
+    weights: HashMap<usize, f32>,
+    ids: Vec<usize>, // Sorted ruleset ids, bit i of a domain stands for ids[i]
+    index_weights: Vec<f32>, // `weights` by domain index
+    topology: Arc<dyn Topology>,
+    compatible: Vec<Vec<Domain>>, // Per direction and tile index, what may sit next to it
+    queue: BinaryHeap<Reverse<(usize, usize, usize)>>, // (entropy, x, y), stale entries are skipped
+    pending: Vec<(usize, usize)>, // Collapsed cells whose neighbours are not narrowed yet
     initial_collapse_done: bool,
+    seed: u64,
+    rng: StdRng,
+    stats: SolveStats,
+    changed: Vec<(usize, usize)>, // Cells touched since the last `take_changed`
+    narrowed: Vec<(usize, usize)>, // Cells whose domain changed since the last connectivity check
+    track_eliminations: bool, // Record why values were removed, for the GUI inspector
+    quiet: bool, // No per-step logging, for batch runs on many threads
+    connectivity: Option<Connectivity>, // Checked after every collapse
+    tileset: Arc<Tileset>, // Tile names and images
+    heights: Option<Arc<Heights>>, // Per-cell bias of the tile weights
+}
+
+// Outcome of a single `Grid::step`
+#[derive(PartialEq)]
+enum Step {
+    Collapsed,
+    Contradiction,
+    Done,
 }
 
 
 impl Grid {
-    fn new(size: usize, tiles: Vec<Tile>, rules: HashMap<usize, Vec<usize>>) -> Result<Self, &'static str> {
-        if tiles.is_empty() {
-            return Err("No tiles provided");
-        }
-        Ok(Self {
-            cells: (0..size)
-                .map(|_| (0..size)
-                .map(|_| {
-                    let tile_id = rand::random::<usize>() % tiles.len();
-                    let mut tile = tiles[tile_id].clone();
-                    tile.value = None; // All tiles start uncollapsed
-                    tile
+    fn new(size: usize, tiles: Vec<usize>, rules: HashMap<usize, Vec<usize>>, seed: u64) -> Result<Self, &'static str> {
+        check_tiles(&tiles, &rules)?;
+        Ok(Self::from_values(vec![vec![None; size]; size], rules, seed))
+    }
+
+    // A size x size x layers block, see `topology::Voxel`
+    fn new_voxel(size: usize, layers: usize, tiles: Vec<usize>, rules: HashMap<usize, Vec<usize>>, seed: u64) -> Result<Self, &'static str> {
+        check_tiles(&tiles, &rules)?;
+        if size == 0 {
+            return Err("Voxel grids need at least one row");
+        }
+        let mut grid = Self::from_values(vec![vec![None; size]; size * layers], rules, seed);
+        grid.set_topology(&TopologyKind::Voxel { rows: size }).map_err(|_| "Voxel grids need at least one layer")?;
+        Ok(grid)
+    }
+
+    // Rebuild a grid from exported ids; `None` cells start with every ruleset id possible
+    fn from_values(values: Vec<Vec<Option<usize>>>, rules: HashMap<usize, Vec<usize>>, seed: u64) -> Self {
+        let mut ids: Vec<usize> = rules.keys().copied().collect();
+        ids.sort_unstable();
+        let weights = get_weights();
+        let index_weights: Vec<f32> = ids.iter().map(|id| weights.get(id).copied().unwrap_or(1.0)).collect();
+
+        let topology = TopologyKind::Square.build(values.len(), values.first().map_or(0, |row| row.len()))
+            .expect("Square grids fit any size");
+        let compatible = undirected_compatibility(&ids, &rules, topology.direction_count());
+
+        let all_values = Domain::full(ids.len());
+        let cells: Vec<Vec<Cell>> = values.into_iter()
+            .map(|row| row.into_iter()
+                .map(|value| match value.and_then(|id| ids.binary_search(&id).ok()) {
+                    Some(index) => Cell::new(value, Domain::single(index), &index_weights),
+                    None => Cell::new(None, all_values, &index_weights),
                 }).collect())
-                .collect(),
+            .collect();
+
+        let mut queue = BinaryHeap::new();
+        let mut pending = vec![];
+        for (x, row) in cells.iter().enumerate() {
+            for (y, cell) in row.iter().enumerate() {
+                match cell.value {
+                    Some(_) => pending.push((x, y)),
+                    None => queue.push(Reverse((cell.domain.len(), x, y))),
+                }
+            }
+        }
+        let initial_collapse_done = !pending.is_empty();
+        let stats = SolveStats::new(cells.len(), cells.first().map_or(0, |row| row.len()));
+        Self {
+            cells,
             rules,
-            initial_collapse_done: false,
-        })
+            weights,
+            ids,
+            index_weights,
+            topology,
+            compatible,
+            queue,
+            pending,
+            initial_collapse_done,
+            seed,
+            rng: StdRng::seed_from_u64(seed),
+            stats,
+            changed: vec![],
+            narrowed: vec![],
+            track_eliminations: false,
+            quiet: false,
+            connectivity: None,
+            tileset: Arc::new(Tileset::terrain()),
+            heights: None,
+        }
+    }
+
+    // Switch to another cell layout before solving; resets any edge rules
+    fn set_topology(&mut self, kind: &TopologyKind) -> Result<(), String> {
+        self.topology = kind.build(self.cells.len(), self.cells.first().map_or(0, |row| row.len()))?;
+        self.compatible = undirected_compatibility(&self.ids, &self.rules, self.topology.direction_count());
+        Ok(())
+    }
+
+    fn set_tileset(&mut self, tileset: Tileset) {
+        self.tileset = Arc::new(tileset);
+    }
+
+    // Replace the tile weights, ids without one weigh 1
+    fn set_weights(&mut self, weights: HashMap<usize, f32>) {
+        self.index_weights = self.ids.iter().map(|id| weights.get(id).copied().unwrap_or(1.0)).collect();
+        self.weights = weights;
+        for cell in self.cells.iter_mut().flatten() {
+            cell.weight_sum = domain_weight(&cell.domain, &self.index_weights);
+        }
+    }
+
+    // Bias tile picks by height, e.g. ocean in the lows and mountains on the highs
+    fn set_heights(&mut self, heights: Heights) {
+        self.heights = Some(Arc::new(heights));
+        // Entropies change with the weights, queued ones would all be stale
+        self.queue.clear();
+        for x in 0..self.cells.len() {
+            for y in 0..self.cells[x].len() {
+                self.queue_cell(x, y);
+            }
+        }
+    }
+
+    // Replace the rules across single edges, for tiles that only fit one way round
+    fn set_edge_rules(&mut self, edge_rules: &EdgeRules) -> Result<(), String> {
+        let directions = self.topology.direction_count();
+        for (id, edges) in edge_rules {
+            let index = self.ids.binary_search(id).map_err(|_| format!("Unknown tile id in edge rules: {}", id))?;
+            if edges.len() != directions {
+                return Err(format!("Tile {} has {} edge rules, a {} grid needs {}", id, edges.len(), self.topology.name(), directions));
+            }
+            for (direction, allowed) in edges.iter().enumerate() {
+                let mut domain = Domain::empty();
+                for neighbour in allowed {
+                    domain.insert(self.ids.binary_search(neighbour).map_err(|_| format!("Unknown tile id in edge rules: {}", neighbour))?);
+                }
+                self.compatible[direction][index] = domain;
+            }
+        }
+        Ok(())
+    }
+
+    // Start over from the current state with a different random sequence
+    fn reseed(&mut self, seed: u64) {
+        self.seed = seed;
+        self.rng = StdRng::seed_from_u64(seed);
+    }
+
+    fn remaining_cells(&self) -> usize {
+        self.cells.iter().flatten().filter(|cell| cell.value.is_none()).count()
     }
 
     fn entropy(&self, x: usize, y: usize) -> usize {
         if let Some(value) = self.cells[x][y].value {
             if value == 0 { usize::MAX } else { 0 }
+        } else if self.heights.is_some() && !self.cells[x][y].domain.is_empty() {
+            self.weighted_entropy(x, y)
         } else {
-            self.cells[x][y].possible_values.len()
+            self.cells[x][y].domain.len()
+        }
+    }
+
+    // Shannon entropy of the height-scaled weights in thousandths, at least 1 for open cells.
+    // Cells where the height clearly favours one tile get collapsed before undecided ones.
+    fn weighted_entropy(&self, x: usize, y: usize) -> usize {
+        let weights: Vec<f32> = self.cells[x][y].domain.iter()
+            .map(|index| self.cell_weight(x, y, index))
+            .filter(|&weight| weight > 0.0)
+            .collect();
+        let total: f32 = weights.iter().sum();
+        if total <= 0.0 {
+            return self.cells[x][y].domain.len();
+        }
+        let entropy: f32 = weights.iter().map(|weight| weight / total).map(|p| -p * p.ln()).sum();
+        (entropy.max(0.0) * 1000.0) as usize + 1
+    }
+
+    fn weight(&self, id: usize) -> f32 {
+        self.weights.get(&id).copied().unwrap_or(1.0)
+    }
+
+    // Ids still possible at a cell, in ascending order
+    fn candidates(&self, x: usize, y: usize) -> Vec<usize> {
+        self.cells[x][y].domain.iter().map(|index| self.ids[index]).collect()
+    }
+
+    // Weight of tile `index` at a cell, scaled by the cell's height if there is a heightmap
+    fn cell_weight(&self, x: usize, y: usize, index: usize) -> f32 {
+        match &self.heights {
+            Some(heights) => self.index_weights[index] * heights.factor(x, y, self.ids[index]),
+            None => self.index_weights[index],
+        }
+    }
+
+    // Weighted random pick among the remaining possible values of a cell, as a domain index
+    fn pick_value(&mut self, x: usize, y: usize) -> usize {
+        let cell = &self.cells[x][y];
+        let weight_sum = match self.heights {
+            Some(_) => cell.domain.iter().map(|index| self.cell_weight(x, y, index)).sum(),
+            None => cell.weight_sum,
+        };
+        if weight_sum > 0.0 {
+            let mut target = self.rng.gen_range(0.0..weight_sum);
+            let mut picked = None;
+            for index in cell.domain.iter().filter(|&index| self.index_weights[index] > 0.0) {
+                picked = Some(index);
+                let weight = self.cell_weight(x, y, index);
+                if target < weight {
+                    break;
+                }
+                target -= weight;
+            }
+            if let Some(index) = picked {
+                return index;
+            }
         }
+        // All candidates weighted zero, fall back to a uniform pick
+        let nth = self.rng.gen_range(0..cell.domain.len());
+        cell.domain.iter().nth(nth).unwrap()
     }
 
-    fn id_to_name(&self, id: u32) -> &'static str {
-        match id {
-            1 => "plains",
-            2 => "forest",
-            3 => "mountains",
-            4 => "dessert",
-            5 => "shore",
-            6 => "ocean",
-            _ => panic!("Invalid ID"),
+    // Put an open cell back into the min-entropy queue after its domain grew or shrank
+    fn queue_cell(&mut self, x: usize, y: usize) {
+        if self.cells[x][y].value.is_none() {
+            self.queue.push(Reverse((self.entropy(x, y), x, y)));
         }
     }
 
+    // Restrict a cell to `allowed`, recording what was removed and why.
+    // Returns true if the domain changed.
+    fn narrow(&mut self, x: usize, y: usize, allowed: &Domain, reason: Elimination) -> bool {
+        let cell = &mut self.cells[x][y];
+        let removed = cell.domain.difference(allowed);
+        if removed.is_empty() {
+            return false;
+        }
+        if self.track_eliminations {
+            cell.eliminated.extend(removed.iter().map(|index| (self.ids[index], reason)));
+        }
+        cell.domain = cell.domain.intersection(allowed);
+        cell.weight_sum = domain_weight(&cell.domain, &self.index_weights);
+        self.narrowed.push((x, y));
+        self.queue_cell(x, y);
+        true
+    }
+
+    fn id_to_name(&self, id: u32) -> &str {
+        self.tileset.names.get(&(id as usize)).expect("Invalid ID")
+    }
+
     fn collapse(&mut self) -> bool {
         if !self.initial_collapse_done {
-            let mid = self.cells.len() / 2;
-            println!("Performing Initial collapse at {}, {}", mid, mid);
-            if !self.cells[mid][mid].possible_values.is_empty() {
-                let idx = rand::thread_rng().gen_range(0..self.cells[mid][mid].possible_values.len());
-                self.cells[mid][mid].value = Some(self.cells[mid][mid].possible_values[idx]);
+            // Grids are not always square, voxel grids stack several layers of rows
+            let (mid_x, mid_y) = (self.cells.len() / 2, self.cells[0].len() / 2);
+            if !self.quiet {
+                println!("Performing Initial collapse at {}, {}", mid_x, mid_y);
+            }
+            if !self.cells[mid_x][mid_y].domain.is_empty() {
+                let entropy = self.entropy(mid_x, mid_y);
+                self.collapse_cell(mid_x, mid_y, entropy);
                 self.initial_collapse_done = true;
                 return true;
             }
         }
-        // Find the cell with the smallest non-zero entropy
-        let (mut min_x, mut min_y, mut min_entropy) = (0, 0, usize::MAX);
-        for (x, row) in self.cells.iter().enumerate() {
-            for (y, _cell) in row.iter().enumerate() {
-                let entropy = self.entropy(x, y);
-                if entropy != 0 && entropy < min_entropy {
-                    min_x = x;
-                    min_y = y;
-                    min_entropy = entropy;
+        // Find the cell with the smallest non-zero entropy, ties go to the first in row-major order
+        while let Some(Reverse((entropy, x, y))) = self.queue.pop() {
+            if self.cells[x][y].value.is_none() && entropy != 0 && entropy == self.entropy(x, y) {
+                self.collapse_cell(x, y, entropy);
+                return true;
+            }
+        }
+        false
+    }
+
+    fn collapse_cell(&mut self, x: usize, y: usize, entropy: usize) {
+        let index = self.pick_value(x, y);
+        let val = self.ids[index];
+        let cell = &mut self.cells[x][y];
+        cell.value = Some(val);
+
+        // Remove other possibilities
+        cell.domain = Domain::single(index);
+        cell.weight_sum = self.index_weights[index];
+
+        self.stats.collapse_step[x][y] = Some(self.stats.steps);
+        self.stats.collapse_entropy[x][y] = Some(entropy);
+        self.stats.steps += 1;
+        // The cell and the neighbours propagate is about to narrow
+        self.narrowed.push((x, y));
+        self.changed.push((x, y));
+        let neighbours = self.neighbours(x, y);
+        self.changed.extend(neighbours);
+        self.pending.push((x, y));
+    }
+
+    // Designer constraint: limit a cell to `allowed`, collapsing it once a single value is left.
+    // Returns false if that leaves the cell or one of its neighbours without any option.
+    fn constrain(&mut self, x: usize, y: usize, allowed: &[usize]) -> bool {
+        let mut mask = Domain::empty();
+        for index in allowed.iter().filter_map(|id| self.ids.binary_search(id).ok()) {
+            mask.insert(index);
+        }
+        // A collapsed cell outside `allowed` loses its only value here
+        self.narrow(x, y, &mask, Elimination::Constraint);
+
+        let cell = &mut self.cells[x][y];
+        if cell.value.is_none() && cell.domain.len() == 1 {
+            cell.value = cell.domain.iter().next().map(|index| self.ids[index]);
+            self.initial_collapse_done = true;
+            self.pending.push((x, y));
+        }
+        let valid = !cell.domain.is_empty();
+
+        self.changed.push((x, y));
+        let neighbours = self.neighbours(x, y);
+        self.changed.extend(neighbours);
+        valid && self.propagate().is_ok()
+    }
+
+    // Reset `region` to uncollapsed cells whose domains only allow what their fixed
+    // neighbours permit, so the next run re-solves just that region. Returns false if
+    // some cell in it cannot take any value next to its neighbours.
+    fn uncollapse(&mut self, region: &[(usize, usize)]) -> bool {
+        for &(x, y) in region {
+            self.cells[x][y] = Cell::new(None, Domain::full(self.ids.len()), &self.index_weights);
+            self.stats.collapse_step[x][y] = None;
+            self.stats.collapse_entropy[x][y] = None;
+            self.changed.push((x, y));
+            self.narrowed.push((x, y));
+            self.queue_cell(x, y);
+        }
+        // The kept cells around the region narrow it down again
+        for &(x, y) in region {
+            for (nx, ny) in self.neighbours(x, y) {
+                if self.cells[nx][ny].value.is_some() {
+                    self.pending.push((nx, ny));
                 }
             }
         }
-        // Collapse that cell
-        if min_entropy != usize::MAX {
-            let idx = rand::thread_rng().gen_range(0..self.cells[min_x][min_y].possible_values.len());
-            self.cells[min_x][min_y].value = Some(self.cells[min_x][min_y].possible_values[idx]);
+        // Never seed into the part of the map that is being kept
+        self.initial_collapse_done = true;
+        self.propagate().is_ok()
+    }
 
-            // Remove other possibilities
-            self.cells[min_x][min_y].possible_values.clear();
-            let val = self.cells[min_x][min_y].value.unwrap();
-            self.cells[min_x][min_y].possible_values.push(val);
-            // self.cells[min_x][min_y].possible_values.push(self.cells[min_x][min_y].value.unwrap());
-            
-            return true;
+    // Re-solve only `region` with a new seed, keeping the rest of the map untouched
+    fn regenerate(&mut self, region: &[(usize, usize)], seed: u64) {
+        println!("Regenerating {} cells with seed {}", region.len(), seed);
+        self.reseed(seed);
+        if !self.uncollapse(region) {
+            println!("Region cannot be regenerated, its border has no compatible tiles");
+            return;
         }
-        false
+        self.run();
     }
-    
+
+    // Every cell in the rectangle between two corners, inclusive and clamped to the grid
+    fn rect_region(&self, (x0, y0): (usize, usize), (x1, y1): (usize, usize)) -> Vec<(usize, usize)> {
+        let rows = self.cells.len();
+        let cols = self.cells.first().map_or(0, |row| row.len());
+        if rows == 0 || cols == 0 {
+            return vec![];
+        }
+        let (x0, x1) = (x0.min(x1).min(rows - 1), x0.max(x1).min(rows - 1));
+        let (y0, y1) = (y0.min(y1).min(cols - 1), y0.max(y1).min(cols - 1));
+        (x0..=x1).flat_map(|x| (y0..=y1).map(move |y| (x, y))).collect()
+    }
+
+    fn neighbours(&self, x: usize, y: usize) -> Vec<(usize, usize)> {
+        self.topology.neighbours(x, y).into_iter().map(|(_, cell)| cell).collect()
+    }
+
+    // Narrow the neighbours of every newly collapsed cell. On a contradiction the
+    // offending cell stays queued, so later calls report it again.
     fn propagate(&mut self) -> Result<(), ()> {
-        let dir = vec![(-1, 0), (1, 0), (0, -1), (0, 1)];  // Up, Down, Left, Right
-    
-        for i in 0..self.cells.len() {
-            for j in 0..self.cells[i].len() {
-                if let Some(value) = self.cells[i][j].value {
-                    for (dx, dy) in &dir {
-                        let nx = i as i32 + dx;
-                        let ny = j as i32 + dy;
-    
-                        if nx >= 0 && nx < self.cells.len() as i32 && ny >= 0 && ny < self.cells[i].len() as i32 {
-                            let nx = nx as usize;
-                            let ny = ny as usize;
-    
-                            if let Some(allowed_values) = self.rules.get(&value) {
-                                self.cells[nx][ny].possible_values.retain(|v| allowed_values.contains(v));
-                            }
-                            // Contradiction handling
-                            if self.cells[nx][ny].possible_values.is_empty() {
-                                println!("Contradiction found at ({}, {})", nx, ny);
-                                return Err(());
-                            }
+        while let Some(&(i, j)) = self.pending.last() {
+            if let Some(value) = self.cells[i][j].value {
+                let index = self.cells[i][j].domain.iter().next();
+                for (direction, (nx, ny)) in self.topology.neighbours(i, j) {
+                    let mut narrowed = false;
+                    if let Some(index) = index {
+                        let allowed = self.compatible[direction][index];
+                        narrowed = self.narrow(nx, ny, &allowed, Elimination::Neighbour { x: i, y: j, value, direction });
+                    }
+                    // Heights collapse cells far apart, the open cells between them need
+                    // to hear about it before either side picks something they cannot bridge
+                    let (cx, cy) = match narrowed && self.heights.is_some() && !self.cells[nx][ny].domain.is_empty() {
+                        true => self.spread(nx, ny),
+                        false => (nx, ny),
+                    };
+                    // Contradiction handling
+                    if self.cells[cx][cy].domain.is_empty() {
+                        if !self.quiet {
+                            println!("Contradiction found at ({}, {})", cx, cy);
                         }
+                        self.stats.contradictions[cx][cy] += 1;
+                        return Err(());
                     }
                 }
             }
+            self.pending.pop();
         }
         Ok(())
     }
 
+    // Narrow open cells outwards from (x, y) to the tiles that still fit next to some option
+    // of their neighbour, until nothing changes. Returns the cell that ran out of options, or (x, y) if none did.
+    fn spread(&mut self, x: usize, y: usize) -> (usize, usize) {
+        let mut stack = vec![(x, y)];
+        while let Some((i, j)) = stack.pop() {
+            for (direction, (nx, ny)) in self.topology.neighbours(i, j) {
+                if self.cells[nx][ny].value.is_some() {
+                    continue;
+                }
+                let mut allowed = Domain::empty();
+                for index in self.cells[i][j].domain.iter() {
+                    allowed = allowed.union(&self.compatible[direction][index]);
+                }
+                if self.cells[nx][ny].domain.difference(&allowed).is_empty() {
+                    continue;
+                }
+                self.narrow(nx, ny, &allowed, Elimination::Unsupported { x: i, y: j, direction });
+                self.changed.push((nx, ny));
+                if self.cells[nx][ny].domain.is_empty() {
+                    return (nx, ny);
+                }
+                stack.push((nx, ny));
+            }
+        }
+        (x, y)
+    }
+
     // Add function to check for contradictions
     fn has_contradiction(&self) -> bool {
-        self.cells.iter().flatten().any(|cell| cell.possible_values.is_empty() && cell.value.is_none())
+        self.cells.iter().flatten().any(|cell| cell.domain.is_empty() && cell.value.is_none())
     }
     
     fn is_fully_collapsed(&self) -> bool {
@@ -269,42 +682,674 @@ impl Grid {
 
     // Modify run function to stop in case of contradictions
     fn run(&mut self) {
+        self.run_observed(0, |_| {});
+    }
+
+    // One solver iteration: collapse a cell and propagate
+    fn step(&mut self) -> Step {
+        let collapsed = self.collapse();
+        if !collapsed && self.is_fully_collapsed() {
+            return Step::Done;
+        }
+        let consistent = self.propagate().is_ok() && self.keep_connected();
+        match consistent {
+            true if collapsed => Step::Collapsed,
+            // Nothing left that could be collapsed although some cells are still open
+            true => Step::Contradiction,
+            false => Step::Contradiction,
+        }
+    }
+
+    // Applies the connectivity constraint, if any; false if the walkable cells got cut apart
+    fn keep_connected(&mut self) -> bool {
+        match self.connectivity.take() {
+            Some(mut connectivity) => {
+                let connected = connectivity.enforce(self);
+                self.connectivity = Some(connectivity);
+                connected
+            }
+            None => {
+                self.narrowed.clear();
+                true
+            }
+        }
+    }
+
+    // Cells whose value or possible values changed since the last call
+    fn take_changed(&mut self) -> Vec<(usize, usize)> {
+        std::mem::take(&mut self.changed)
+    }
+
+    // Same as `run` without the progress bar; true if every cell got a value
+    fn solve(&mut self) -> bool {
+        loop {
+            match self.step() {
+                Step::Collapsed => self.changed.clear(),
+                Step::Contradiction => return false,
+                Step::Done => return true,
+            }
+        }
+    }
+
+    // Same as `run`, but hands the grid to `observe` after every `every` collapsed
+    // cells (0 = never) and once more when solving stops, unless it just saw that state
+    fn run_observed(&mut self, every: usize, mut observe: impl FnMut(&Grid)) {
         let total_cells = self.cells.len() * self.cells[0].len();
         let mut pb = ProgressBar::new(total_cells as u64);
+        // Redrawing the bar on every cell would cost more than solving large grids
+        pb.set_max_refresh_rate(Some(Duration::from_millis(100)));
+        let mut collapsed_cells = self.cells.iter().flatten().filter(|cell| cell.value.is_some()).count() as u64;
+        let mut observed = false;
 
-        while !self.is_fully_collapsed() {
-            if self.collapse() {
-                pb.inc();
-            }
-            if let Err(()) = self.propagate() {  // Handle error from propagate
-                pb.finish_print("Grid collapsing ended with a contradiction.");
-                return;
+        loop {
+            match self.step() {
+                Step::Collapsed => {
+                    collapsed_cells += 1;
+                    pb.set(collapsed_cells);
+                    observed = every > 0 && self.stats.steps.is_multiple_of(every);
+                    if observed {
+                        observe(self);
+                    }
+                }
+                Step::Contradiction => {
+                    observe(self);
+                    pb.finish_print("Grid collapsing ended with a contradiction.");
+                    return;
+                }
+                Step::Done => break,
             }
+            self.changed.clear();
+        }
+        // The last collapse finished the grid, nothing changed since
+        if !observed {
+            observe(self);
         }
         pb.finish_print("Grid collapsing completed.");
     }
 }
 
-// And then modify the `main` function:
+// Command line options, e.g. `wave_collapse --seed 42 --json map.json --csv map.csv`
+struct Options {
+    seed: u64,
+    size: usize,
+    json_path: Option<String>,
+    csv_path: Option<String>,
+    raw_path: Option<String>,
+    tmx_path: Option<String>,
+    tiled_json_path: Option<String>,
+    import_path: Option<String>,
+    record_path: Option<String>,
+    record_every: Option<usize>,
+    frame_delay_ms: u32,
+    heatmap_dir: Option<String>,
+    heatmap_overlay: bool,
+    gui: bool,
+    steps_per_frame: usize,
+    paused: bool,
+    regen_rect: Option<String>,
+    regen_mask: Option<String>,
+    regen_seed: Option<u64>,
+    skip_image: bool,
+    batch: usize,
+    batch_dir: String,
+    threads: usize,
+    chunks: Option<String>,
+    chunk_size: usize,
+    hex: bool,
+    wrap: bool,
+    edge_rules: Option<String>,
+    layers: usize,
+    vox_path: Option<String>,
+    blocks_path: Option<String>,
+    graph_path: Option<String>,
+    graph_json_path: Option<String>,
+    connected: bool,
+    connect_points: Option<String>,
+    walkable: Option<String>,
+    overlays: Vec<String>,
+    coarse: usize,
+    heightmap: Option<String>,
+    noise: bool,
+    noise_scale: f32,
+    learn_path: Option<String>,
+    learn_out: String,
+    tileset_path: Option<String>,
+    validate: bool,
+    rules_dot: Option<String>,
+    rules_matrix: Option<String>,
+}
+
+fn parse_number<T: std::str::FromStr>(flag: &str, value: String) -> T {
+    value.parse().unwrap_or_else(|_| {
+        eprintln!("Invalid value for {}: {}", flag, value);
+        process::exit(1);
+    })
+}
+
+fn parse_args() -> Options {
+    let mut options = Options {
+        seed: rand::random(),
+        size: 85,
+        json_path: None,
+        csv_path: None,
+        raw_path: None,
+        tmx_path: None,
+        tiled_json_path: None,
+        import_path: None,
+        record_path: None,
+        record_every: None,
+        frame_delay_ms: 50,
+        heatmap_dir: None,
+        heatmap_overlay: false,
+        gui: false,
+        steps_per_frame: 10,
+        paused: false,
+        regen_rect: None,
+        regen_mask: None,
+        regen_seed: None,
+        skip_image: false,
+        batch: 0,
+        batch_dir: "batch".to_string(),
+        threads: std::thread::available_parallelism().map_or(1, |n| n.get()),
+        chunks: None,
+        chunk_size: 32,
+        hex: false,
+        wrap: false,
+        edge_rules: None,
+        layers: 0,
+        vox_path: None,
+        blocks_path: None,
+        graph_path: None,
+        graph_json_path: None,
+        connected: false,
+        connect_points: None,
+        walkable: None,
+        overlays: vec![],
+        coarse: 0,
+        heightmap: None,
+        noise: false,
+        noise_scale: 24.0,
+        learn_path: None,
+        learn_out: "learned.json".to_string(),
+        tileset_path: None,
+        validate: false,
+        rules_dot: None,
+        rules_matrix: None,
+    };
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().unwrap_or_else(|| {
+            eprintln!("Missing value for {}", arg);
+            process::exit(1);
+        });
+        match arg.as_str() {
+            "--seed" => options.seed = parse_number(&arg, value()),
+            "--size" => options.size = parse_number(&arg, value()),
+            "--json" => options.json_path = Some(value()),
+            "--csv" => options.csv_path = Some(value()),
+            "--raw" => options.raw_path = Some(value()),
+            "--tmx" => options.tmx_path = Some(value()),
+            "--tiled-json" => options.tiled_json_path = Some(value()),
+            "--import" => options.import_path = Some(value()),
+            "--record" => options.record_path = Some(value()),
+            "--record-every" => options.record_every = Some(parse_number(&arg, value())),
+            "--frame-delay" => options.frame_delay_ms = parse_number(&arg, value()),
+            "--heatmaps" => options.heatmap_dir = Some(value()),
+            "--heatmap-overlay" => options.heatmap_overlay = true,
+            "--gui" => options.gui = true,
+            "--steps-per-frame" => options.steps_per_frame = parse_number(&arg, value()),
+            "--paused" => options.paused = true,
+            "--regen-rect" => options.regen_rect = Some(value()),
+            "--regen-mask" => options.regen_mask = Some(value()),
+            "--regen-seed" => options.regen_seed = Some(parse_number(&arg, value())),
+            "--no-image" => options.skip_image = true,
+            "--batch" => options.batch = parse_number(&arg, value()),
+            "--batch-dir" => options.batch_dir = value(),
+            "--threads" => options.threads = parse_number(&arg, value()),
+            "--chunks" => options.chunks = Some(value()),
+            "--chunk-size" => options.chunk_size = parse_number(&arg, value()),
+            "--hex" => options.hex = true,
+            "--wrap" => options.wrap = true,
+            "--edge-rules" => options.edge_rules = Some(value()),
+            "--layers" => options.layers = parse_number(&arg, value()),
+            "--vox" => options.vox_path = Some(value()),
+            "--blocks-json" => options.blocks_path = Some(value()),
+            "--graph" => options.graph_path = Some(value()),
+            "--graph-json" => options.graph_json_path = Some(value()),
+            "--connected" => options.connected = true,
+            "--connect" => options.connect_points = Some(value()),
+            "--walkable" => options.walkable = Some(value()),
+            "--overlay" => options.overlays.push(value()),
+            "--coarse" => options.coarse = parse_number(&arg, value()),
+            "--heightmap" => options.heightmap = Some(value()),
+            "--noise" => options.noise = true,
+            "--noise-scale" => options.noise_scale = parse_number(&arg, value()),
+            "--learn" => options.learn_path = Some(value()),
+            "--learn-out" => options.learn_out = value(),
+            "--tileset" => options.tileset_path = Some(value()),
+            "--validate" => options.validate = true,
+            "--rules-dot" => options.rules_dot = Some(value()),
+            "--rules-matrix" => options.rules_matrix = Some(value()),
+            _ => {
+                eprintln!("Unknown argument: {}", arg);
+                process::exit(1);
+            }
+        }
+    }
+    options
+}
+
+// Cells to regenerate from `--regen-rect row0,col0,row1,col1` or `--regen-mask mask.png`,
+// where every non-black mask pixel marks a cell (the mask is stretched over the grid)
+fn regen_region(grid: &Grid, options: &Options) -> Result<Vec<(usize, usize)>, String> {
+    let mut region = vec![];
+    if let Some(rect) = &options.regen_rect {
+        let corners: Vec<usize> = rect.split(',')
+            .map(|n| n.trim().parse().map_err(|_| format!("Invalid region: {}", rect)))
+            .collect::<Result<_, _>>()?;
+        if corners.len() != 4 {
+            return Err(format!("Region needs row0,col0,row1,col1: {}", rect));
+        }
+        region.extend(grid.rect_region((corners[0], corners[1]), (corners[2], corners[3])));
+    }
+    if let Some(mask_path) = &options.regen_mask {
+        let mask = image::open(mask_path).map_err(|e| format!("{:?}", e))?.into_luma8();
+        let rows = grid.cells.len();
+        let cols = grid.cells.first().map_or(0, |row| row.len());
+        for x in 0..rows {
+            for y in 0..cols {
+                let pixel = mask.get_pixel((y * mask.width() as usize / cols) as u32, (x * mask.height() as usize / rows) as u32);
+                if pixel[0] > 0 && !region.contains(&(x, y)) {
+                    region.push((x, y));
+                }
+            }
+        }
+    }
+    Ok(region)
+}
+
+// Corners from `--chunks cx0,cy0,cx1,cy1`
+fn parse_chunk_area(area: &str) -> Result<(chunks::ChunkCoord, chunks::ChunkCoord), String> {
+    let corners: Vec<i64> = area.split(',')
+        .map(|n| n.trim().parse().map_err(|_| format!("Invalid chunk area: {}", area)))
+        .collect::<Result<_, _>>()?;
+    match corners[..] {
+        [cx0, cy0, cx1, cy1] => Ok(((cx0, cy0), (cx1, cy1))),
+        _ => Err(format!("Chunk area needs cx0,cy0,cx1,cy1: {}", area)),
+    }
+}
 
 fn main() {
     println!("Initializing Program...");
+    let options = parse_args();
     let current_dir = env::current_dir().unwrap();
-    let possible_values = vec![1, 2, 3, 4, 5, 6]; 
-    let tiles = load_tiles(possible_values.clone(), &current_dir);
     let rules = get_ruleset();
-    let grid_result = Grid::new(85, tiles, rules);
+    let manifest = options.tileset_path.as_ref().map(|path| {
+        tileset::Manifest::load(Path::new(path)).unwrap_or_else(|e| {
+            eprintln!("Failed to load tileset manifest {}: {}", path, e);
+            process::exit(1);
+        })
+    });
+    let kind = if options.hex {
+        TopologyKind::Hex
+    } else if options.wrap {
+        TopologyKind::Wrap
+    } else {
+        TopologyKind::Square
+    };
+
+    if let Some(path) = &options.learn_path {
+        // Derive rules and weights from an example map instead of generating one
+        let tileset = manifest.as_ref().map_or_else(Tileset::terrain, |manifest| manifest.tileset());
+        let saved = learn::learn(Path::new(path), &tileset, &kind)
+            .and_then(|learned| learned.save(Path::new(&options.learn_out)).map_err(|e| e.to_string()));
+        if let Err(e) = saved {
+            eprintln!("Failed to learn rules from {}: {}", path, e);
+            process::exit(1);
+        }
+        return;
+    }
+
+    if options.validate {
+        // Lint the ruleset for CI instead of generating a map
+        let mut checked = manifest.clone().unwrap_or_else(tileset::Manifest::terrain);
+        if let Some(path) = &options.edge_rules {
+            match topology::load_edge_rules(Path::new(path)) {
+                Ok(edge_rules) => checked.edge_rules = Some(edge_rules),
+                Err(e) => {
+                    eprintln!("Failed to load edge rules from {}: {}", path, e);
+                    process::exit(1);
+                }
+            }
+        }
+        match validate::validate(&checked, &kind) {
+            Ok(0) => println!("No problems found"),
+            Ok(problems) => {
+                println!("{} problem(s) found", problems);
+                process::exit(1);
+            }
+            Err(e) => {
+                eprintln!("Failed to validate rules: {}", e);
+                process::exit(1);
+            }
+        }
+        return;
+    }
 
-    let mut grid = match grid_result {
-        Ok(g) => g,
-        Err(e) => {
+    let graph_file = options.graph_path.as_ref().map(|path| {
+        graph::load_graph(Path::new(path)).unwrap_or_else(|e| {
+            eprintln!("Failed to load graph from {}: {}", path, e);
+            process::exit(1);
+        })
+    });
+
+    let mut grid = if let Some(import_path) = &options.import_path {
+        // Re-render or re-export a previously exported map instead of generating one
+        let path = Path::new(import_path);
+        let source = manifest.clone().unwrap_or_else(tileset::Manifest::terrain);
+        let imported = if path.extension().is_some_and(|ext| ext == "csv") {
+            export::import_csv(path, &source, &kind, options.seed)
+        } else {
+            export::import_json(path, &source)
+        };
+        match imported {
+            Ok(g) => g,
+            Err(e) => {
+                eprintln!("Failed to import {}: {}", import_path, e);
+                process::exit(1);
+            }
+        }
+    } else if let Some(area) = &options.chunks {
+        // Stream a block of chunks of an endless world and treat them as one finished map
+        let (from, to) = parse_chunk_area(area).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            process::exit(1);
+        });
+        // Chunks are plain squares, stitched together along their edges
+        if options.hex || options.wrap || options.layers > 0 || options.heightmap.is_some() {
+            eprintln!("Chunked worlds cannot be combined with --hex, --wrap, --layers or --heightmap");
+            process::exit(1);
+        }
+        // Every chunk starts out as a copy of this one, set up like any other grid
+        let template = match &manifest {
+            Some(manifest) => manifest.grid(options.chunk_size, options.chunk_size, &TopologyKind::Square, options.seed),
+            None => Grid::new(options.chunk_size, load_tiles(&current_dir), rules, options.seed).map_err(String::from),
+        };
+        let mut template = template.unwrap_or_else(|e| {
+            eprintln!("Failed to create chunk: {}", e);
+            process::exit(1);
+        });
+        if template.cells.is_empty() {
+            eprintln!("Chunks need a size of at least 1");
+            process::exit(1);
+        }
+        if let Some(path) = &options.edge_rules {
+            let applied = topology::load_edge_rules(Path::new(path))
+                .map_err(|e| e.to_string())
+                .and_then(|edge_rules| template.set_edge_rules(&edge_rules));
+            if let Err(e) = applied {
+                eprintln!("Failed to load edge rules from {}: {}", path, e);
+                process::exit(1);
+            }
+        }
+        println!("Using world seed {}", options.seed);
+        chunks::World::new(options.seed, template).area(from, to)
+    } else if let Some(graph_file) = &graph_file {
+        // One cell per node of an arbitrary graph, see `graph::GraphFile`
+        if options.hex || options.wrap || options.layers > 0 {
+            eprintln!("Graphs cannot be combined with --hex, --wrap or --layers");
+            process::exit(1);
+        }
+        check_tiles(&load_tiles(&current_dir), &rules).unwrap_or_else(|e| {
             eprintln!("Failed to create grid: {}", e);
-            return;
+            process::exit(1);
+        });
+        println!("Using seed {}", options.seed);
+        graph::graph_grid(graph_file, rules, options.seed).unwrap_or_else(|e| {
+            eprintln!("Failed to load graph: {}", e);
+            process::exit(1);
+        })
+    } else if let Some(manifest) = &manifest {
+        // Tiles and rules of a saved manifest instead of the built-in terrain
+        if options.layers > 0 {
+            eprintln!("Tileset manifests cannot be layered");
+            process::exit(1);
+        }
+        println!("Using tileset {} with seed {}", manifest.name, options.seed);
+        manifest.grid(options.size, options.size, &kind, options.seed).unwrap_or_else(|e| {
+            eprintln!("Failed to create grid: {}", e);
+            process::exit(1);
+        })
+    } else {
+        let tiles = load_tiles(&current_dir);
+        println!("Using seed {}", options.seed);
+        let grid_result = if options.layers > 0 {
+            Grid::new_voxel(options.size, options.layers, tiles, rules, options.seed)
+        } else {
+            Grid::new(options.size, tiles, rules, options.seed)
+        };
+
+        match grid_result {
+            Ok(g) => g,
+            Err(e) => {
+                eprintln!("Failed to create grid: {}", e);
+                return;
+            }
+        }
+    };
+
+    if manifest.is_some() {
+        // Already laid out by `Manifest::grid`, which also applied its edge rules
+    } else if options.hex {
+        if options.layers > 0 {
+            eprintln!("Hex grids cannot be layered");
+            process::exit(1);
+        }
+        grid.set_topology(&TopologyKind::Hex).expect("Hex grids fit any size");
+    } else if options.wrap {
+        if options.layers > 0 {
+            eprintln!("Wrapping grids cannot be layered");
+            process::exit(1);
+        }
+        grid.set_topology(&TopologyKind::Wrap).expect("Wrapping grids fit any size");
+    }
+    if let Some(path) = &options.edge_rules {
+        let applied = topology::load_edge_rules(Path::new(path))
+            .map_err(|e| e.to_string())
+            .and_then(|edge_rules| grid.set_edge_rules(&edge_rules));
+        if let Err(e) = applied {
+            eprintln!("Failed to load edge rules from {}: {}", path, e);
+            process::exit(1);
+        }
+    }
+
+    if options.rules_dot.is_some() || options.rules_matrix.is_some() {
+        // Show the rules as the solver sees them, with edge rules applied, instead of solving
+        if let Some(path) = &options.rules_dot {
+            if let Err(e) = ruleset::export_dot(&grid, Path::new(path)) {
+                eprintln!("Failed to export rules graph: {}", e);
+                process::exit(1);
+            }
+        }
+        if let Some(path) = &options.rules_matrix {
+            if let Err(e) = ruleset::render_matrix(&grid, Path::new(path)) {
+                eprintln!("Failed to render compatibility matrix: {:?}", e);
+                process::exit(1);
+            }
         }
+        return;
+    }
+
+    // Walkable tiles that have to stay connected, checked once more after solving
+    let connectivity = (options.connected || options.connect_points.is_some()).then(|| {
+        let walkable = match &options.walkable {
+            Some(ids) => connectivity::parse_ids(ids),
+            None => Ok(connectivity::DEFAULT_WALKABLE.to_vec()),
+        };
+        let points = options.connect_points.as_deref().map_or(Ok(vec![]), connectivity::parse_points);
+        walkable.and_then(|walkable| points.map(|points| (walkable, points))).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            process::exit(1);
+        })
+    });
+    // Imported and chunked maps arrive already solved
+    let solved = options.import_path.is_some() || options.chunks.is_some();
+    let (rows, cols) = grid.topology.size();
+    let heights = if let Some(path) = &options.heightmap {
+        match Heights::from_image(Path::new(path), rows, cols) {
+            Ok(heights) => Some(heights),
+            Err(e) => {
+                eprintln!("Failed to load heightmap {}: {:?}", path, e);
+                process::exit(1);
+            }
+        }
+    } else if options.noise {
+        println!("Using {} cell noise as heightmap", options.noise_scale);
+        Some(Heights::from_noise(grid.seed, rows, cols, options.noise_scale))
+    } else {
+        None
     };
+    if let Some(heights) = heights {
+        // Tile ids of a manifest need not be the built-in terrain
+        match &manifest {
+            Some(manifest) => grid.set_heights(heights.for_manifest(manifest)),
+            None => grid.set_heights(heights),
+        }
+    }
+    if options.coarse > 0 && !solved {
+        // Large-scale structure from a biome map solved first
+        if let Some(manifest) = &manifest {
+            // The biomes are made of the built-in terrain tiles
+            eprintln!("--coarse only works with the built-in terrain tiles, not with tileset {}", manifest.name);
+            process::exit(1);
+        }
+        if let Err(e) = hierarchy::constrain_to_biomes(&mut grid, options.coarse) {
+            eprintln!("Failed to generate biome map: {}", e);
+            process::exit(1);
+        }
+    }
+    if let (Some((walkable, points)), false) = (&connectivity, solved) {
+        if let Err(e) = connectivity::constrain(&mut grid, walkable, points.clone(), options.connected) {
+            eprintln!("Failed to apply connectivity constraint: {}", e);
+            process::exit(1);
+        }
+    }
+
+    if options.batch > 0 {
+        // Many independent maps with consecutive seeds instead of a single one
+        if let Err(e) = batch::run_batch(&grid, options.batch, options.threads, Path::new(&options.batch_dir)) {
+            eprintln!("Batch generation failed: {:?}", e);
+            process::exit(1);
+        }
+        return;
+    }
+
+    if options.regen_rect.is_some() || options.regen_mask.is_some() {
+        // Keep the imported (or freshly generated) map and re-solve part of it
+        if !solved {
+            grid.run();
+        }
+        match regen_region(&grid, &options) {
+            Ok(region) => grid.regenerate(&region, options.regen_seed.unwrap_or_else(rand::random)),
+            Err(e) => {
+                eprintln!("{}", e);
+                process::exit(1);
+            }
+        }
+    } else if options.gui {
+        // Solve live in a window instead, imported maps continue from where they stopped
+        if let Err(e) = gui::run(grid, options.steps_per_frame, options.paused) {
+            eprintln!("Failed to start GUI: {}", e);
+            process::exit(1);
+        }
+        return;
+    } else if !solved {
+        if let Some(record_path) = &options.record_path {
+            // Timelapse of the solve, one frame every `record_every` collapsed cells or a fixed number of frames
+            if let Err(e) = animation::record_run(&mut grid, Path::new(record_path), options.record_every, options.frame_delay_ms) {
+                eprintln!("Failed to record frames: {:?}", e);
+            }
+        } else {
+            grid.run();
+        }
+    }
 
-    grid.run();
-    match stitch_images(&grid) {
+    // Decoration layers on top of the finished terrain, each from its own manifest
+    let layers: Vec<layers::Layer> = options.overlays.iter().enumerate()
+        .map(|(index, path)| {
+            let manifest = tileset::Manifest::load(Path::new(path)).map_err(|e| format!("{}: {}", path, e))?;
+            layers::generate(&grid, &manifest, grid.seed.wrapping_add(index as u64 + 1))
+        })
+        .collect::<Result<_, String>>()
+        .unwrap_or_else(|e| {
+            eprintln!("Failed to generate layer: {}", e);
+            process::exit(1);
+        });
+
+    if let Some((walkable, points)) = &connectivity {
+        if !connectivity::report(&grid, walkable, points, options.connected) {
+            println!("Walkable tiles are not connected as requested");
+        }
+    }
+
+    if let Some(path) = &options.json_path {
+        if let Err(e) = export::export_json(&grid, Path::new(path)) {
+            eprintln!("Failed to export JSON: {}", e);
+        }
+        // Layers next to the terrain, as `<map>_<layer>.json`
+        for layer in &layers {
+            let path = Path::new(path);
+            let stem = path.file_stem().map_or_else(|| "map".into(), |stem| stem.to_string_lossy());
+            let layer_path = path.with_file_name(format!("{}_{}.json", stem, layer.name));
+            if let Err(e) = export::export_json(&layer.grid, &layer_path) {
+                eprintln!("Failed to export layer {}: {}", layer.name, e);
+            }
+        }
+    }
+    if let Some(path) = &options.csv_path {
+        if let Err(e) = export::export_csv(&grid, Path::new(path)) {
+            eprintln!("Failed to export CSV: {}", e);
+        }
+    }
+    if let Some(path) = &options.raw_path {
+        if let Err(e) = export::export_raw(&grid, Path::new(path)) {
+            eprintln!("Failed to export raw ids: {}", e);
+        }
+    }
+    if let Some(path) = &options.tmx_path {
+        if let Err(e) = tiled::export_tmx(&grid, Path::new(path)) {
+            eprintln!("Failed to export Tiled map: {}", e);
+        }
+    }
+    if let Some(path) = &options.tiled_json_path {
+        if let Err(e) = tiled::export_tiled_json(&grid, Path::new(path)) {
+            eprintln!("Failed to export Tiled JSON map: {}", e);
+        }
+    }
+    if let Some(path) = &options.vox_path {
+        if let Err(e) = voxel::export_vox(&grid, Path::new(path)) {
+            eprintln!("Failed to export MagicaVoxel model: {}", e);
+        }
+    }
+    if let Some(path) = &options.blocks_path {
+        if let Err(e) = voxel::export_blocks_json(&grid, Path::new(path)) {
+            eprintln!("Failed to export block list: {}", e);
+        }
+    }
+    if let (Some(graph_file), Some(path)) = (&graph_file, &options.graph_json_path) {
+        if let Err(e) = graph::export_graph_json(&grid, graph_file, Path::new(path)) {
+            eprintln!("Failed to export graph solution: {}", e);
+        }
+    }
+    if let Some(dir) = &options.heatmap_dir {
+        if let Err(e) = heatmap::write_heatmaps(&grid, Path::new(dir), options.heatmap_overlay) {
+            eprintln!("Failed to write heatmaps: {:?}", e);
+        }
+    }
+    if options.skip_image {
+        return;
+    }
+    match stitch_images(&grid, &layers) {
         Ok(_) => println!("Image stitching completed successfully."),
         Err(e) => println!("Failed to stitch images: {:?}", e),
     }
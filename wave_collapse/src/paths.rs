@@ -0,0 +1,61 @@
+use std::env;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+// `target` as seen from `dir`, e.g. `../tileset`, so files pointing at each other keep working
+// when their folder is moved. Works on the path text alone, neither path has to exist yet;
+// relative ones are taken from the working directory.
+pub fn relative_path(target: &Path, dir: &Path) -> io::Result<PathBuf> {
+    let current_dir = env::current_dir()?;
+    let target = normalize(&current_dir.join(target));
+    let dir = normalize(&current_dir.join(dir));
+    let common = target.components().zip(dir.components()).take_while(|(a, b)| a == b).count();
+    // Different drives on Windows have nothing in common to walk up to
+    if common == 0 {
+        return Ok(target);
+    }
+    let mut relative: PathBuf = dir.components().skip(common).map(|_| Component::ParentDir).collect();
+    relative.extend(target.components().skip(common));
+    if relative.as_os_str().is_empty() {
+        relative.push(".");
+    }
+    Ok(relative)
+}
+
+// Drops `.` and resolves `..` against the component before it, without looking at the disk
+fn normalize(path: &Path) -> PathBuf {
+    let mut normal = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normal.pop();
+            }
+            other => normal.push(other),
+        }
+    }
+    normal
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn walks_up_to_the_common_directory() {
+        let relative = relative_path(Path::new("/maps/tileset/1_plains.png"), Path::new("/maps/out/level1")).unwrap();
+        assert_eq!(relative, Path::new("../../tileset/1_plains.png"));
+    }
+
+    #[test]
+    fn works_for_paths_that_do_not_exist() {
+        let relative = relative_path(Path::new("no/such/tileset"), Path::new("no/such/layers/../out")).unwrap();
+        assert_eq!(relative, Path::new("../tileset"));
+    }
+
+    #[test]
+    fn same_directory_is_dot() {
+        assert_eq!(relative_path(Path::new("layers/./decorations"), Path::new("layers/decorations")).unwrap(), Path::new("."));
+        assert_eq!(relative_path(Path::new("tileset"), Path::new("")).unwrap(), Path::new("tileset"));
+    }
+}
@@ -0,0 +1,191 @@
+use image::{ImageResult, Pixel, Rgba, RgbaImage};
+use std::collections::HashMap;
+use crate::Grid;
+use crate::topology::Layout;
+
+// Drawn for uncollapsed cells whose candidates have no image
+const PLACEHOLDER: Rgba<u8> = Rgba([40, 40, 40, 255]);
+// Drawn for cells left without any possible value
+const CONTRADICTION: Rgba<u8> = Rgba([255, 0, 255, 255]);
+
+// Every tile image of a grid's ruleset, loaded once instead of once per cell
+pub struct TileImages {
+    images: HashMap<usize, RgbaImage>,
+    pub tile_width: u32,
+    pub tile_height: u32,
+}
+
+impl TileImages {
+    pub fn load(grid: &Grid) -> ImageResult<Self> {
+        let mut images = HashMap::new();
+        for &id in grid.rules.keys() {
+            images.insert(id, image::open(grid.tileset.image_path(id))?.into_rgba8());
+        }
+        let tile_width = images.values().map(|img| img.width()).max().unwrap_or(0);
+        let tile_height = images.values().map(|img| img.height()).max().unwrap_or(0);
+        Ok(Self { images, tile_width, tile_height })
+    }
+
+    pub fn get(&self, id: usize) -> Option<&RgbaImage> {
+        self.images.get(&id)
+    }
+}
+
+// Vertical distance between hex rows, which overlap by a quarter tile
+fn hex_row_step(images: &TileImages) -> u32 {
+    images.tile_height * 3 / 4
+}
+
+// Top left corner of a cell's tile image on the canvas; odd hex rows are shifted half a tile right
+pub fn cell_origin(grid: &Grid, images: &TileImages, row: usize, col: usize) -> (u32, u32) {
+    match grid.topology.layout() {
+        // Voxel layers are drawn one below the other, graph nodes at their storage position
+        Layout::Square => (col as u32 * images.tile_width, row as u32 * images.tile_height),
+        Layout::Hex => (col as u32 * images.tile_width + (row as u32 % 2) * images.tile_width / 2, row as u32 * hex_row_step(images)),
+    }
+}
+
+pub fn canvas_size(grid: &Grid, images: &TileImages) -> (u32, u32) {
+    let rows = grid.cells.len() as u32;
+    let cols = grid.cells.first().map_or(0, |row| row.len()) as u32;
+    match grid.topology.layout() {
+        Layout::Square => (cols * images.tile_width, rows * images.tile_height),
+        Layout::Hex if rows == 0 => (0, 0),
+        Layout::Hex => {
+            let shift = if rows > 1 { images.tile_width / 2 } else { 0 };
+            (cols * images.tile_width + shift, (rows - 1) * hex_row_step(images) + images.tile_height)
+        }
+    }
+}
+
+// Whether pixel (x, y) of a tile image belongs to the cell; hex cells cut off the corners
+pub fn covers(grid: &Grid, images: &TileImages, x: u32, y: u32) -> bool {
+    match grid.topology.layout() {
+        Layout::Square => true,
+        Layout::Hex => {
+            let half_width = images.tile_width as f32 / 2.0;
+            let half_height = images.tile_height as f32 / 2.0;
+            let dx = (x as f32 + 0.5 - half_width).abs();
+            let dy = (y as f32 + 0.5 - half_height).abs();
+            dx <= half_width && dy <= half_height - dx * half_height / images.tile_width as f32
+        }
+    }
+}
+
+// The cell drawn at canvas position (x, y)
+pub fn cell_at(grid: &Grid, images: &TileImages, x: f32, y: f32) -> Option<(usize, usize)> {
+    let rows = grid.cells.len();
+    let cols = grid.cells.first().map_or(0, |row| row.len());
+    if x < 0.0 || y < 0.0 || images.tile_width == 0 || images.tile_height == 0 {
+        return None;
+    }
+    let row_step = match grid.topology.layout() {
+        Layout::Square => images.tile_height,
+        Layout::Hex => hex_row_step(images),
+    };
+    // Overlapping hex rows: the point is in this row or the one above
+    let row = (y / row_step as f32) as usize;
+    for row in [row, row.wrapping_sub(1)].into_iter().filter(|&row| row < rows) {
+        let (shift, top) = cell_origin(grid, images, row, 0);
+        if x < shift as f32 {
+            continue;
+        }
+        let col = ((x - shift as f32) / images.tile_width as f32) as usize;
+        if col >= cols {
+            continue;
+        }
+        let (left, _) = cell_origin(grid, images, row, col);
+        let (px, py) = ((x - left as f32) as u32, (y - top as f32) as u32);
+        if py < images.tile_height && covers(grid, images, px, py) {
+            return Some((row, col));
+        }
+    }
+    None
+}
+
+fn fill_cell(canvas: &mut RgbaImage, grid: &Grid, images: &TileImages, top_left_x: u32, top_left_y: u32, colour: Rgba<u8>) {
+    for y in 0..images.tile_height {
+        for x in 0..images.tile_width {
+            if covers(grid, images, x, y) {
+                canvas.put_pixel(top_left_x + x, top_left_y + y, colour);
+            }
+        }
+    }
+}
+
+// Superposition preview: every remaining candidate blended pixel by pixel, scaled by its weight
+fn blend_candidates(canvas: &mut RgbaImage, grid: &Grid, images: &TileImages, candidates: &[usize], top_left_x: u32, top_left_y: u32) {
+    let weighted: Vec<(&RgbaImage, f32)> = candidates.iter()
+        .filter_map(|&id| images.get(id).map(|img| (img, grid.weight(id))))
+        .filter(|&(_, weight)| weight > 0.0)
+        .collect();
+    let total_weight: f32 = weighted.iter().map(|&(_, weight)| weight).sum();
+    if total_weight <= 0.0 {
+        fill_cell(canvas, grid, images, top_left_x, top_left_y, PLACEHOLDER);
+        return;
+    }
+
+    for y in 0..images.tile_height {
+        for x in (0..images.tile_width).filter(|&x| covers(grid, images, x, y)) {
+            let mut sum = [0.0f32; 4];
+            for &(img, weight) in &weighted {
+                let pixel = img.get_pixel(x.min(img.width() - 1), y.min(img.height() - 1));
+                for (channel, value) in sum.iter_mut().zip(pixel.0.iter()) {
+                    *channel += *value as f32 * weight;
+                }
+            }
+            let blended = sum.map(|channel| (channel / total_weight).round() as u8);
+            canvas.put_pixel(top_left_x + x, top_left_y + y, Rgba(blended));
+        }
+    }
+}
+
+// Redraws a single cell of an image produced by `render_grid`
+pub fn draw_cell(canvas: &mut RgbaImage, grid: &Grid, images: &TileImages, row: usize, col: usize) {
+    let cell = &grid.cells[row][col];
+    let (top_left_x, top_left_y) = cell_origin(grid, images, row, col);
+
+    if cell.domain.is_empty() {
+        fill_cell(canvas, grid, images, top_left_x, top_left_y, CONTRADICTION);
+    } else if let Some(value) = cell.value {
+        fill_cell(canvas, grid, images, top_left_x, top_left_y, PLACEHOLDER);
+        if let Some(tile_image) = images.get(value) {
+            for (x, y, pixel) in tile_image.enumerate_pixels().filter(|&(x, y, _)| covers(grid, images, x, y)) {
+                canvas.get_pixel_mut(top_left_x + x, top_left_y + y).blend(pixel);
+            }
+        }
+    } else {
+        blend_candidates(canvas, grid, images, &grid.candidates(row, col), top_left_x, top_left_y);
+    }
+}
+
+// Renders the grid in any state: collapsed tiles, blended superpositions and contradictions
+pub fn render_grid(grid: &Grid, images: &TileImages) -> RgbaImage {
+    let (width, height) = canvas_size(grid, images);
+    let mut canvas = RgbaImage::new(width, height);
+    for (row, cells) in grid.cells.iter().enumerate() {
+        for col in 0..cells.len() {
+            draw_cell(&mut canvas, grid, images, row, col);
+        }
+    }
+    canvas
+}
+
+// Collapsed tiles and contradictions only, on a transparent canvas, for drawing over another grid
+pub fn render_overlay(grid: &Grid, images: &TileImages) -> RgbaImage {
+    let (width, height) = canvas_size(grid, images);
+    let mut canvas = RgbaImage::new(width, height);
+    for (row, cells) in grid.cells.iter().enumerate() {
+        for (col, cell) in cells.iter().enumerate() {
+            let (top_left_x, top_left_y) = cell_origin(grid, images, row, col);
+            if cell.domain.is_empty() {
+                fill_cell(&mut canvas, grid, images, top_left_x, top_left_y, CONTRADICTION);
+            } else if let Some(tile_image) = cell.value.and_then(|value| images.get(value)) {
+                for (x, y, pixel) in tile_image.enumerate_pixels().filter(|&(x, y, _)| covers(grid, images, x, y)) {
+                    canvas.put_pixel(top_left_x + x, top_left_y + y, *pixel);
+                }
+            }
+        }
+    }
+    canvas
+}
@@ -0,0 +1,139 @@
+use image::{imageops, ImageResult, Rgba, RgbaImage};
+use image::imageops::FilterType;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use crate::font;
+use crate::render::TileImages;
+use crate::Grid;
+
+// Side of a matrix cell and of the tile thumbnails on the axes
+const CELL: u32 = 16;
+const MARGIN: u32 = 8;
+// Space between the per-direction panels
+const GAP: u32 = 8;
+const LINE: u32 = font::GLYPH_HEIGHT + 4;
+
+const BACKGROUND: Rgba<u8> = Rgba([24, 24, 24, 255]);
+const TEXT: Rgba<u8> = Rgba([230, 230, 230, 255]);
+const BOTH_WAYS: Rgba<u8> = Rgba([90, 170, 80, 255]);
+const ONE_WAY: Rgba<u8> = Rgba([230, 150, 40, 255]);
+const NOT_ALLOWED: Rgba<u8> = Rgba([55, 55, 55, 255]);
+
+// Directions of `a` that tile `b` may sit on, by domain index, straight from `Grid::compatible`
+fn sides(grid: &Grid, a: usize, b: usize) -> Vec<usize> {
+    (0..grid.topology.direction_count())
+        .filter(|&direction| grid.compatible[direction][a].iter().any(|index| index == b))
+        .collect()
+}
+
+fn side_names(grid: &Grid, sides: &[usize]) -> String {
+    sides.iter().map(|&direction| grid.topology.direction_name(direction)).collect::<Vec<_>>().join(", ")
+}
+
+// Writes the rules as a Graphviz graph, one node per tile. Pairs that fit the same way
+// from both ends get one double-headed edge, labelled with the sides unless it is all of
+// them; rules only one of the two tiles has are drawn as dashed red arrows.
+pub fn export_dot(grid: &Grid, path: &Path) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    let directions = grid.topology.direction_count();
+    writeln!(writer, "digraph rules {{")?;
+    writeln!(writer, "    node [shape=box, labelloc=b];")?;
+    for &id in &grid.ids {
+        let label = format!("{} {}", id, grid.id_to_name(id as u32));
+        let image = grid.tileset.image_path(id).display().to_string();
+        writeln!(writer, "    t{} [label={:?}, image={:?}];", id, label, image)?;
+    }
+    for a in 0..grid.ids.len() {
+        for b in a..grid.ids.len() {
+            let (forward, backward) = (sides(grid, a, b), sides(grid, b, a));
+            let mut mirrored: Vec<usize> = forward.iter().map(|&direction| grid.topology.opposite(direction)).collect();
+            mirrored.sort_unstable();
+            let edge = format!("t{} -> t{}", grid.ids[a], grid.ids[b]);
+            if !forward.is_empty() && mirrored == backward {
+                match forward.len() == directions {
+                    true => writeln!(writer, "    {} [dir=both];", edge)?,
+                    false => writeln!(writer, "    {} [dir=both, label={:?}];", edge, side_names(grid, &forward))?,
+                }
+                continue;
+            }
+            if !forward.is_empty() {
+                writeln!(writer, "    {} [style=dashed, color=red, label={:?}];", edge, side_names(grid, &forward))?;
+            }
+            if !backward.is_empty() {
+                let edge = format!("t{} -> t{}", grid.ids[b], grid.ids[a]);
+                writeln!(writer, "    {} [style=dashed, color=red, label={:?}];", edge, side_names(grid, &backward))?;
+            }
+        }
+    }
+    writeln!(writer, "}}")?;
+    println!("Exported rules graph to {}", path.display());
+    Ok(())
+}
+
+fn fill(canvas: &mut RgbaImage, x: u32, y: u32, width: u32, height: u32, colour: Rgba<u8>) {
+    for py in y..y + height {
+        for px in x..x + width {
+            canvas.put_pixel(px, py, colour);
+        }
+    }
+}
+
+// One panel per direction: the row tile with the column tile on that side of it.
+// Green pairs fit from both ends, orange ones only have a rule on the row tile's side.
+pub fn render_matrix(grid: &Grid, path: &Path) -> ImageResult<()> {
+    let images = TileImages::load(grid)?;
+    let thumbnails: Vec<Option<RgbaImage>> = grid.ids.iter()
+        .map(|&id| images.get(id).map(|image| imageops::resize(image, CELL, CELL, FilterType::Nearest)))
+        .collect();
+    let tiles = grid.ids.len() as u32;
+    let directions = grid.topology.direction_count() as u32;
+    let panel = (tiles + 1) * CELL;
+
+    let title = "Rows: tile, columns: its neighbour on that side";
+    let legend = [(BOTH_WAYS, "both ways"), (ONE_WAY, "one way"), (NOT_ALLOWED, "not allowed")];
+    let legend_width: u32 = legend.iter().map(|(_, text)| font::GLYPH_HEIGHT + 4 + font::text_width(text) + GAP * 2).sum();
+    let width = (directions * panel + directions.saturating_sub(1) * GAP).max(font::text_width(title)).max(legend_width) + MARGIN * 2;
+    let height = MARGIN + LINE * 2 + panel + LINE / 2 + LINE + MARGIN;
+    let mut canvas = RgbaImage::from_pixel(width, height, BACKGROUND);
+    font::draw_text(&mut canvas, MARGIN, MARGIN, title, TEXT);
+
+    let top = MARGIN + LINE * 2;
+    for direction in 0..directions {
+        let left = MARGIN + direction * (panel + GAP);
+        font::draw_text(&mut canvas, left, MARGIN + LINE, grid.topology.direction_name(direction as usize), TEXT);
+        for (index, thumbnail) in thumbnails.iter().enumerate() {
+            if let Some(thumbnail) = thumbnail {
+                let offset = (index as u32 + 1) * CELL;
+                imageops::overlay(&mut canvas, thumbnail, left + offset, top);
+                imageops::overlay(&mut canvas, thumbnail, left, top + offset);
+            }
+        }
+        let opposite = grid.topology.opposite(direction as usize);
+        for a in 0..grid.ids.len() {
+            for b in 0..grid.ids.len() {
+                let colour = match (
+                    grid.compatible[direction as usize][a].iter().any(|index| index == b),
+                    grid.compatible[opposite][b].iter().any(|index| index == a),
+                ) {
+                    (true, true) => BOTH_WAYS,
+                    (true, false) => ONE_WAY,
+                    _ => NOT_ALLOWED,
+                };
+                // One pixel of background between cells as grid lines
+                fill(&mut canvas, left + (b as u32 + 1) * CELL + 1, top + (a as u32 + 1) * CELL + 1, CELL - 1, CELL - 1, colour);
+            }
+        }
+    }
+
+    let mut x = MARGIN;
+    let y = top + panel + LINE / 2;
+    for (colour, text) in legend {
+        fill(&mut canvas, x, y, font::GLYPH_HEIGHT, font::GLYPH_HEIGHT, colour);
+        font::draw_text(&mut canvas, x + font::GLYPH_HEIGHT + 4, y, text, TEXT);
+        x += font::GLYPH_HEIGHT + 4 + font::text_width(text) + GAP * 2;
+    }
+    canvas.save(path)?;
+    println!("Saved compatibility matrix to {}", path.display());
+    Ok(())
+}
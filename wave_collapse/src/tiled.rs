@@ -0,0 +1,47 @@
+use std::io;
+use std::path::Path;
+use crate::Grid;
+use crate::tiled_map::{self, TiledMap, TiledTile};
+use crate::topology::Layout;
+
+// Every ruleset id in ascending order, with its name and image
+fn tileset_tiles(grid: &Grid, map_path: &Path) -> io::Result<Vec<TiledTile>> {
+    let mut ids: Vec<usize> = grid.rules.keys().copied().collect();
+    ids.sort_unstable();
+    let tiles = ids.into_iter().map(|id| (id, grid.id_to_name(id as u32).to_string(), grid.tileset.image_path(id)));
+    tiled_map::tileset_tiles(tiles, map_path)
+}
+
+// Global tile ids in row-major order, 0 for uncollapsed cells. The solver never turns
+// tiles, so there are no flip flags; tileset_collapse exports its oriented tiles with them.
+fn layer_data(grid: &Grid, tiles: &[TiledTile]) -> Vec<u32> {
+    grid.cells.iter().flatten()
+        .map(|cell| match cell.value.and_then(|v| tiles.iter().position(|t| t.id == v)) {
+            Some(index) => index as u32 + 1,
+            None => 0,
+        })
+        .collect()
+}
+
+fn tiled_map(grid: &Grid, path: &Path) -> io::Result<TiledMap> {
+    let tiles = tileset_tiles(grid, path)?;
+    Ok(TiledMap {
+        tileset: "wave_collapse",
+        layer: "terrain",
+        width: grid.cells.first().map_or(0, |row| row.len()),
+        height: grid.cells.len(),
+        hex: matches!(grid.topology.layout(), Layout::Hex),
+        data: layer_data(grid, &tiles),
+        tiles,
+    })
+}
+
+// Writes a .tmx map with an image collection tileset pointing at `tileset/`
+pub fn export_tmx(grid: &Grid, path: &Path) -> io::Result<()> {
+    tiled_map(grid, path)?.write_tmx(path)
+}
+
+// Same map as `export_tmx`, in Tiled's JSON map format
+pub fn export_tiled_json(grid: &Grid, path: &Path) -> io::Result<()> {
+    tiled_map(grid, path)?.write_json(path)
+}
@@ -0,0 +1,204 @@
+use serde_json::json;
+use std::env;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use crate::paths::relative_path;
+
+const TILED_VERSION: &str = "1.10";
+
+// Tileset entries; the local Tiled tile id is the index into the list
+pub struct TiledTile {
+    pub id: usize,
+    pub name: String,
+    // Relative to the map, with forward slashes
+    pub image: String,
+    pub width: u32,
+    pub height: u32,
+}
+
+// One tile layer over an image collection tileset, as written for the Tiled editor. Shared
+// with tileset_collapse, so it knows tile ids and images but neither crate's `Grid`.
+pub struct TiledMap {
+    // Name of the tileset and of the tile layer
+    pub tileset: &'static str,
+    pub layer: &'static str,
+    pub width: usize,
+    pub height: usize,
+    // Odd rows shifted right by half a tile, like the odd-r hex layout of wave_collapse
+    pub hex: bool,
+    pub tiles: Vec<TiledTile>,
+    // Global tile ids in row-major order, 0 for empty cells, flip flags in the top bits
+    pub data: Vec<u32>,
+}
+
+// Reads the size of each `(id, name, image)` and makes its path relative to `map_path`.
+// Relative image paths are taken from the current directory.
+pub fn tileset_tiles(tiles: impl IntoIterator<Item = (usize, String, PathBuf)>, map_path: &Path) -> io::Result<Vec<TiledTile>> {
+    let current_dir = env::current_dir()?;
+    let map_dir = current_dir.join(map_path).parent().map_or_else(|| current_dir.clone(), Path::to_path_buf);
+    tiles.into_iter()
+        .map(|(id, name, image)| {
+            let tile_path = current_dir.join(image);
+            let (width, height) = image::image_dimensions(&tile_path)
+                .map_err(|e| io::Error::new(io::ErrorKind::NotFound, format!("{}: {}", tile_path.display(), e)))?;
+            let image = relative_path(&tile_path, &map_dir)?.to_string_lossy().replace('\\', "/");
+            Ok(TiledTile { id, name, image, width, height })
+        })
+        .collect()
+}
+
+// Text safe to put between double quotes in an XML attribute
+fn escape(value: &str) -> String {
+    value.replace('&', "&amp;").replace('"', "&quot;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+impl TiledMap {
+    fn tile_size(&self) -> (u32, u32) {
+        let tile_width = self.tiles.iter().map(|t| t.width).max().unwrap_or(0);
+        let tile_height = self.tiles.iter().map(|t| t.height).max().unwrap_or(0);
+        (tile_width, tile_height)
+    }
+
+    // Tiled orientation name, plus the hex side length for hex maps
+    fn orientation(&self) -> (&'static str, Option<u32>) {
+        match self.hex {
+            false => ("orthogonal", None),
+            true => ("hexagonal", Some(self.tile_size().1 / 2)),
+        }
+    }
+
+    // Writes a .tmx map with the tile images referenced from the tileset
+    pub fn write_tmx(&self, path: &Path) -> io::Result<()> {
+        let (tile_width, tile_height) = self.tile_size();
+        let (orientation, hex_side) = self.orientation();
+        let hex_attributes = hex_side
+            .map(|side| format!(r#" hexsidelength="{}" staggeraxis="y" staggerindex="odd""#, side))
+            .unwrap_or_default();
+
+        let mut writer = BufWriter::new(File::create(path)?);
+        writeln!(writer, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+        writeln!(writer, r#"<map version="{}" orientation="{}" renderorder="right-down" width="{}" height="{}" tilewidth="{}" tileheight="{}"{} infinite="0" nextlayerid="2" nextobjectid="1">"#,
+            TILED_VERSION, orientation, self.width, self.height, tile_width, tile_height, hex_attributes)?;
+        writeln!(writer, r#" <tileset firstgid="1" name="{}" tilewidth="{}" tileheight="{}" tilecount="{}" columns="0">"#,
+            escape(self.tileset), tile_width, tile_height, self.tiles.len())?;
+        writeln!(writer, r#"  <grid orientation="orthogonal" width="1" height="1"/>"#)?;
+        for (index, tile) in self.tiles.iter().enumerate() {
+            writeln!(writer, r#"  <tile id="{}">"#, index)?;
+            writeln!(writer, "   <properties>")?;
+            writeln!(writer, r#"    <property name="wfc_id" type="int" value="{}"/>"#, tile.id)?;
+            writeln!(writer, r#"    <property name="name" value="{}"/>"#, escape(&tile.name))?;
+            writeln!(writer, "   </properties>")?;
+            writeln!(writer, r#"   <image width="{}" height="{}" source="{}"/>"#, tile.width, tile.height, escape(&tile.image))?;
+            writeln!(writer, "  </tile>")?;
+        }
+        writeln!(writer, " </tileset>")?;
+        writeln!(writer, r#" <layer id="1" name="{}" width="{}" height="{}">"#, escape(self.layer), self.width, self.height)?;
+        writeln!(writer, r#"  <data encoding="csv">"#)?;
+        for (y, row) in self.data.chunks(self.width.max(1)).enumerate() {
+            let line: Vec<String> = row.iter().map(|gid| gid.to_string()).collect();
+            let separator = if y + 1 < self.height { "," } else { "" };
+            writeln!(writer, "{}{}", line.join(","), separator)?;
+        }
+        writeln!(writer, "</data>")?;
+        writeln!(writer, " </layer>")?;
+        writeln!(writer, "</map>")?;
+        println!("Exported Tiled map to {}", path.display());
+        Ok(())
+    }
+
+    // Same map as `write_tmx`, in Tiled's JSON map format
+    pub fn write_json(&self, path: &Path) -> io::Result<()> {
+        let (tile_width, tile_height) = self.tile_size();
+        let (orientation, hex_side) = self.orientation();
+
+        let mut map = json!({
+            "type": "map",
+            "version": TILED_VERSION,
+            "orientation": orientation,
+            "renderorder": "right-down",
+            "width": self.width,
+            "height": self.height,
+            "tilewidth": tile_width,
+            "tileheight": tile_height,
+            "infinite": false,
+            "nextlayerid": 2,
+            "nextobjectid": 1,
+            "layers": [{
+                "id": 1,
+                "name": self.layer,
+                "type": "tilelayer",
+                "x": 0,
+                "y": 0,
+                "width": self.width,
+                "height": self.height,
+                "opacity": 1,
+                "visible": true,
+                "data": self.data,
+            }],
+            "tilesets": [{
+                "firstgid": 1,
+                "name": self.tileset,
+                "tilewidth": tile_width,
+                "tileheight": tile_height,
+                "tilecount": self.tiles.len(),
+                "columns": 0,
+                "margin": 0,
+                "spacing": 0,
+                "grid": { "orientation": "orthogonal", "width": 1, "height": 1 },
+                "tiles": self.tiles.iter().enumerate().map(|(index, tile)| json!({
+                    "id": index,
+                    "image": tile.image,
+                    "imagewidth": tile.width,
+                    "imageheight": tile.height,
+                    "properties": [
+                        { "name": "wfc_id", "type": "int", "value": tile.id },
+                        { "name": "name", "type": "string", "value": tile.name },
+                    ],
+                })).collect::<Vec<_>>(),
+            }],
+        });
+
+        if let Some(side) = hex_side {
+            map["hexsidelength"] = json!(side);
+            map["staggeraxis"] = json!("y");
+            map["staggerindex"] = json!("odd");
+        }
+
+        let writer = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(writer, &map)?;
+        println!("Exported Tiled JSON map to {}", path.display());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn attribute_values_are_escaped() {
+        assert_eq!(escape(r#"rock & "roll" <1>"#), "rock &amp; &quot;roll&quot; &lt;1&gt;");
+    }
+
+    #[test]
+    fn tmx_escapes_names_and_image_paths() {
+        let map = TiledMap {
+            tileset: "tiles",
+            layer: "terrain",
+            width: 1,
+            height: 1,
+            hex: false,
+            tiles: vec![TiledTile { id: 1, name: "salt & \"pepper\"".to_string(), image: "a<b>/1.png".to_string(), width: 8, height: 8 }],
+            data: vec![1],
+        };
+        let path = env::temp_dir().join(format!("tiled_map_{}_escape.tmx", std::process::id()));
+        map.write_tmx(&path).unwrap();
+        let tmx = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert!(tmx.contains(r#"<property name="name" value="salt &amp; &quot;pepper&quot;"/>"#), "{}", tmx);
+        assert!(tmx.contains(r#"source="a&lt;b&gt;/1.png""#), "{}", tmx);
+    }
+}
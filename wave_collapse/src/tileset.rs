@@ -0,0 +1,142 @@
+use serde::{Serialize, Deserialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::path::{Path, PathBuf};
+use crate::Grid;
+use crate::domain::MAX_TILES;
+use crate::paths::relative_path;
+use crate::topology::{EdgeRules, TopologyKind};
+
+// The terrain tiles in `tileset/`, used unless a manifest says otherwise
+const TERRAIN_TILES: [(usize, &str); 6] = [
+    (1, "plains"),
+    (2, "forest"),
+    (3, "mountains"),
+    (4, "dessert"),
+    (5, "shore"),
+    (6, "ocean"),
+];
+
+// Where the images of a grid's tiles are and what its ids are called
+pub struct Tileset {
+    pub dir: PathBuf,
+    pub names: HashMap<usize, String>,
+}
+
+impl Tileset {
+    pub fn terrain() -> Self {
+        Self {
+            dir: PathBuf::from("tileset"),
+            names: TERRAIN_TILES.iter().map(|&(id, name)| (id, name.to_string())).collect(),
+        }
+    }
+
+    // `<dir>/<id>_<name>.png`
+    pub fn image_path(&self, id: usize) -> PathBuf {
+        let name = self.names.get(&id).map_or("unknown", String::as_str);
+        self.dir.join(format!("{}_{}.png", id, name))
+    }
+}
+
+// A tileset together with its rules, saved as JSON for reuse, e.g. `layers/decorations.json`
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Manifest {
+    pub name: String,
+    // Directory of `<id>_<name>.png` images, relative to the manifest
+    pub tileset: PathBuf,
+    pub tiles: BTreeMap<usize, String>,
+    // Ids allowed next to each tile on any side, like `get_ruleset()`
+    pub rules: BTreeMap<usize, Vec<usize>>,
+    // Ids allowed per side, in the direction order of the topology, for tiles that only fit one way round
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub edge_rules: Option<EdgeRules>,
+    #[serde(default)]
+    pub weights: BTreeMap<usize, f32>,
+    // Terrain ids each tile may be placed on when stacked as a layer, unlisted tiles go anywhere
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub terrain: BTreeMap<usize, Vec<usize>>,
+}
+
+impl Manifest {
+    // The built-in terrain tiles with `get_ruleset()` and `get_weights()`
+    pub fn terrain() -> Self {
+        Self {
+            name: "terrain".to_string(),
+            tileset: PathBuf::from("tileset"),
+            tiles: TERRAIN_TILES.iter().map(|&(id, name)| (id, name.to_string())).collect(),
+            rules: crate::get_ruleset().into_iter().collect(),
+            edge_rules: None,
+            weights: crate::get_weights().into_iter().collect(),
+            terrain: BTreeMap::new(),
+        }
+    }
+
+    // Reads a manifest, with `tileset` resolved against the manifest's directory
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let mut manifest: Manifest = serde_json::from_reader(File::open(path)?)?;
+        manifest.tileset = path.parent().unwrap_or(Path::new("")).join(&manifest.tileset);
+        Ok(manifest)
+    }
+
+    // Writes the manifest with `tileset` relative to the manifest's directory, so `load` finds it again
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut manifest = self.clone();
+        manifest.tileset = relative_path(&self.tileset, path.parent().unwrap_or(Path::new("")))?;
+        serde_json::to_writer_pretty(BufWriter::new(File::create(path)?), &manifest)?;
+        println!("Saved tileset manifest to {}", path.display());
+        Ok(())
+    }
+
+    pub fn tileset(&self) -> Tileset {
+        Tileset {
+            dir: self.tileset.clone(),
+            names: self.tiles.iter().map(|(&id, name)| (id, name.clone())).collect(),
+        }
+    }
+
+    // An empty rows x cols grid of this tileset
+    pub fn grid(&self, rows: usize, cols: usize, topology: &TopologyKind, seed: u64) -> Result<Grid, String> {
+        self.grid_from_values(vec![vec![None; cols]; rows], topology, seed)
+    }
+
+    // A grid of this tileset with some cells already set, e.g. an imported map
+    pub fn grid_from_values(&self, values: Vec<Vec<Option<usize>>>, topology: &TopologyKind, seed: u64) -> Result<Grid, String> {
+        if let Some(id) = self.rules.keys().find(|id| !self.tiles.contains_key(id)) {
+            return Err(format!("Tile {} of {} has no name", id, self.name));
+        }
+        // A domain has one bit per tile
+        if self.rules.len() > MAX_TILES {
+            return Err(format!("{} has {} tiles, at most {} are supported", self.name, self.rules.len(), MAX_TILES));
+        }
+        let rules: HashMap<usize, Vec<usize>> = self.rules.iter().map(|(&id, allowed)| (id, allowed.clone())).collect();
+        let mut grid = Grid::from_values(values, rules, seed);
+        grid.set_tileset(self.tileset());
+        grid.set_weights(self.weights.iter().map(|(&id, &weight)| (id, weight)).collect());
+        grid.set_topology(topology)?;
+        if let Some(edge_rules) = &self.edge_rules {
+            grid.set_edge_rules(edge_rules)?;
+        }
+        Ok(grid)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn too_many_tiles_is_an_error() {
+        let ids = 0..=MAX_TILES;
+        let manifest = Manifest {
+            name: "big".to_string(),
+            tileset: PathBuf::from("big"),
+            tiles: ids.clone().map(|id| (id, format!("tile{}", id))).collect(),
+            rules: ids.clone().map(|id| (id, ids.clone().collect())).collect(),
+            edge_rules: None,
+            weights: BTreeMap::new(),
+            terrain: BTreeMap::new(),
+        };
+        assert!(manifest.grid(2, 2, &TopologyKind::Square, 1).is_err());
+    }
+}
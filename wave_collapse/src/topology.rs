@@ -0,0 +1,379 @@
+use serde::{Serialize, Deserialize};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+// Per tile id, the ids allowed across each edge, in the direction order of the topology
+pub type EdgeRules = BTreeMap<usize, Vec<Vec<usize>>>;
+
+// How cells of a grid touch each other. Cells are always stored as rows x columns
+// (`Grid::cells`), the topology decides which of them are neighbours and across which
+// edge, so the solver never needs to know the shape of the map.
+pub trait Topology: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    // Serializable form, written to map exports
+    fn describe(&self) -> TopologyKind;
+
+    // How renderers place the cells
+    fn layout(&self) -> Layout {
+        Layout::Square
+    }
+
+    // Rows and columns of the cell storage
+    fn size(&self) -> (usize, usize);
+
+    fn direction_count(&self) -> usize;
+
+    fn direction_name(&self, direction: usize) -> &str;
+
+    // The direction leading back from a neighbour
+    fn opposite(&self, direction: usize) -> usize;
+
+    // Cells sharing an edge with (row, col), and the direction each of them lies in
+    fn neighbours(&self, row: usize, col: usize) -> Vec<(usize, (usize, usize))>;
+
+    // Flat index of a cell, row-major over the storage
+    fn index(&self, row: usize, col: usize) -> usize {
+        row * self.size().1 + col
+    }
+
+    fn position(&self, index: usize) -> (usize, usize) {
+        let cols = self.size().1.max(1);
+        (index / cols, index % cols)
+    }
+}
+
+// Where renderers draw a cell
+#[derive(Clone, Copy, PartialEq)]
+pub enum Layout {
+    Square,
+    // Odd rows shifted half a tile right, rows overlapping by a quarter tile
+    Hex,
+}
+
+// A topology as stored in map files; `build` turns it back into one for a rows x cols grid
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TopologyKind {
+    #[default]
+    Square,
+    // Square with the left edge touching the right one and the top touching the bottom
+    Wrap,
+    Hex,
+    Voxel { rows: usize },
+    // Per flat cell index, (label, node) of every edge; labels are the directions
+    Graph { labels: Vec<String>, opposites: Vec<usize>, edges: Vec<Vec<(usize, usize)>> },
+}
+
+impl TopologyKind {
+    pub fn build(&self, rows: usize, cols: usize) -> Result<Arc<dyn Topology>, String> {
+        Ok(match self {
+            TopologyKind::Square => Arc::new(Square { rows, cols, wrap: false }),
+            TopologyKind::Wrap => Arc::new(Square { rows, cols, wrap: true }),
+            TopologyKind::Hex => Arc::new(Hex { rows, cols }),
+            TopologyKind::Voxel { rows: layer_rows } => {
+                if *layer_rows == 0 || !rows.is_multiple_of(*layer_rows) {
+                    return Err(format!("{} rows cannot be split into layers of {}", rows, layer_rows));
+                }
+                Arc::new(Voxel { rows, cols, layer_rows: *layer_rows })
+            }
+            TopologyKind::Graph { labels, opposites, edges } => {
+                Arc::new(Graph::new(rows, cols, labels.clone(), opposites.clone(), edges.clone())?)
+            }
+        })
+    }
+}
+
+fn inside(rows: usize, cols: usize, row: isize, col: isize) -> Option<(usize, usize)> {
+    if row >= 0 && col >= 0 && (row as usize) < rows && (col as usize) < cols {
+        Some((row as usize, col as usize))
+    } else {
+        None
+    }
+}
+
+// (row + dx, col + dy) if it lies inside the grid, wrapping around if `wrap` is set
+fn offset(rows: usize, cols: usize, row: usize, col: usize, (dx, dy): (isize, isize), wrap: bool) -> Option<(usize, usize)> {
+    let (row, col) = (row as isize + dx, col as isize + dy);
+    if wrap && rows > 0 && cols > 0 {
+        return Some((row.rem_euclid(rows as isize) as usize, col.rem_euclid(cols as isize) as usize));
+    }
+    inside(rows, cols, row, col)
+}
+
+const SQUARE_DIRECTIONS: [(isize, isize); 4] = [(-1, 0), (1, 0), (0, -1), (0, 1)];
+const SQUARE_NAMES: [&str; 4] = ["north", "south", "west", "east"];
+
+// Four neighbours: North, South, West, East
+pub struct Square {
+    rows: usize,
+    cols: usize,
+    wrap: bool,
+}
+
+impl Topology for Square {
+    fn name(&self) -> &'static str {
+        if self.wrap { "wrapping square" } else { "square" }
+    }
+
+    fn describe(&self) -> TopologyKind {
+        if self.wrap { TopologyKind::Wrap } else { TopologyKind::Square }
+    }
+
+    fn size(&self) -> (usize, usize) {
+        (self.rows, self.cols)
+    }
+
+    fn direction_count(&self) -> usize {
+        SQUARE_DIRECTIONS.len()
+    }
+
+    fn direction_name(&self, direction: usize) -> &str {
+        SQUARE_NAMES[direction]
+    }
+
+    fn opposite(&self, direction: usize) -> usize {
+        direction ^ 1
+    }
+
+    fn neighbours(&self, row: usize, col: usize) -> Vec<(usize, (usize, usize))> {
+        SQUARE_DIRECTIONS.iter().enumerate()
+            .filter_map(|(direction, &step)| offset(self.rows, self.cols, row, col, step, self.wrap).map(|cell| (direction, cell)))
+            .collect()
+    }
+}
+
+// Axial (q, r) steps in the direction order of `Hex`
+const HEX_DIRECTIONS: [(isize, isize); 6] = [(1, 0), (1, -1), (0, -1), (-1, 0), (-1, 1), (0, 1)];
+const HEX_NAMES: [&str; 6] = ["east", "north-east", "north-west", "west", "south-west", "south-east"];
+
+// Pointy-top hexagons in "odd-r" offset rows, every odd row shifted half a cell right.
+// Six neighbours: East, North-East, North-West, West, South-West, South-East
+pub struct Hex {
+    rows: usize,
+    cols: usize,
+}
+
+impl Topology for Hex {
+    fn name(&self) -> &'static str {
+        "hex"
+    }
+
+    fn describe(&self) -> TopologyKind {
+        TopologyKind::Hex
+    }
+
+    fn layout(&self) -> Layout {
+        Layout::Hex
+    }
+
+    fn size(&self) -> (usize, usize) {
+        (self.rows, self.cols)
+    }
+
+    fn direction_count(&self) -> usize {
+        HEX_DIRECTIONS.len()
+    }
+
+    fn direction_name(&self, direction: usize) -> &str {
+        HEX_NAMES[direction]
+    }
+
+    fn opposite(&self, direction: usize) -> usize {
+        (direction + 3) % 6
+    }
+
+    fn neighbours(&self, row: usize, col: usize) -> Vec<(usize, (usize, usize))> {
+        let (q, r) = offset_to_axial(row as isize, col as isize);
+        HEX_DIRECTIONS.iter().enumerate()
+            .filter_map(|(direction, &(dq, dr))| {
+                let (row, col) = axial_to_offset(q + dq, r + dr);
+                inside(self.rows, self.cols, row, col).map(|cell| (direction, cell))
+            })
+            .collect()
+    }
+}
+
+// Offset (row, column) of an odd-r hex grid to axial (q, r)
+pub fn offset_to_axial(row: isize, col: isize) -> (isize, isize) {
+    (col - (row - (row & 1)) / 2, row)
+}
+
+// Axial (q, r) back to offset (row, column)
+pub fn axial_to_offset(q: isize, r: isize) -> (isize, isize) {
+    (r, q + (r - (r & 1)) / 2)
+}
+
+// (row, column, layer) steps in the direction order of `Voxel`
+const VOXEL_DIRECTIONS: [(isize, isize, isize); 6] = [(-1, 0, 0), (1, 0, 0), (0, -1, 0), (0, 1, 0), (0, 0, 1), (0, 0, -1)];
+const VOXEL_NAMES: [&str; 6] = ["north", "south", "west", "east", "up", "down"];
+
+// A 3D block stored as square layers of `layer_rows` rows each, bottom layer first.
+// Six neighbours: North, South, West, East, Up (next layer), Down (previous layer)
+pub struct Voxel {
+    rows: usize,
+    cols: usize,
+    layer_rows: usize,
+}
+
+impl Topology for Voxel {
+    fn name(&self) -> &'static str {
+        "voxel"
+    }
+
+    fn describe(&self) -> TopologyKind {
+        TopologyKind::Voxel { rows: self.layer_rows }
+    }
+
+    fn size(&self) -> (usize, usize) {
+        (self.rows, self.cols)
+    }
+
+    fn direction_count(&self) -> usize {
+        VOXEL_DIRECTIONS.len()
+    }
+
+    fn direction_name(&self, direction: usize) -> &str {
+        VOXEL_NAMES[direction]
+    }
+
+    fn opposite(&self, direction: usize) -> usize {
+        direction ^ 1
+    }
+
+    fn neighbours(&self, row: usize, col: usize) -> Vec<(usize, (usize, usize))> {
+        let (layer, layer_row) = (row / self.layer_rows, row % self.layer_rows);
+        let layers = self.rows / self.layer_rows;
+        VOXEL_DIRECTIONS.iter().enumerate()
+            .filter_map(|(direction, &(dx, dy, dz))| {
+                let (layer_row, col) = offset(self.layer_rows, self.cols, layer_row, col, (dx, dy), false)?;
+                let (layer, _) = inside(layers, 1, layer as isize + dz, 0)?;
+                Some((direction, (layer * self.layer_rows + layer_row, col)))
+            })
+            .collect()
+    }
+}
+
+// Any adjacency, e.g. rooms of a dungeon or provinces that share a border. Node i is the
+// cell at flat index i, every edge label is a direction with its own opposite.
+pub struct Graph {
+    rows: usize,
+    cols: usize,
+    labels: Vec<String>,
+    opposites: Vec<usize>,
+    edges: Vec<Vec<(usize, usize)>>,
+}
+
+impl Graph {
+    pub fn new(rows: usize, cols: usize, labels: Vec<String>, opposites: Vec<usize>, edges: Vec<Vec<(usize, usize)>>) -> Result<Self, String> {
+        if edges.len() > rows * cols {
+            return Err(format!("{} graph nodes do not fit into {}x{} cells", edges.len(), rows, cols));
+        }
+        if opposites.len() != labels.len() || opposites.iter().any(|&opposite| opposite >= labels.len()) {
+            return Err("Every edge label needs an opposite label".to_string());
+        }
+        for &(label, node) in edges.iter().flatten() {
+            if label >= labels.len() {
+                return Err(format!("Edge with unknown label {}", label));
+            }
+            if node >= edges.len() {
+                return Err(format!("Edge to unknown node {}", node));
+            }
+        }
+        Ok(Self { rows, cols, labels, opposites, edges })
+    }
+}
+
+impl Topology for Graph {
+    fn name(&self) -> &'static str {
+        "graph"
+    }
+
+    fn describe(&self) -> TopologyKind {
+        TopologyKind::Graph { labels: self.labels.clone(), opposites: self.opposites.clone(), edges: self.edges.clone() }
+    }
+
+    fn size(&self) -> (usize, usize) {
+        (self.rows, self.cols)
+    }
+
+    fn direction_count(&self) -> usize {
+        self.labels.len()
+    }
+
+    fn direction_name(&self, direction: usize) -> &str {
+        &self.labels[direction]
+    }
+
+    fn opposite(&self, direction: usize) -> usize {
+        self.opposites[direction]
+    }
+
+    fn neighbours(&self, row: usize, col: usize) -> Vec<(usize, (usize, usize))> {
+        self.edges.get(self.index(row, col)).into_iter().flatten()
+            .map(|&(label, node)| (label, self.position(node)))
+            .collect()
+    }
+}
+
+// Reads directional rules, e.g. `{ "1": [[1, 2], [1], [1, 2, 3], [1], [1, 2], [1]] }`
+pub fn load_edge_rules(path: &Path) -> io::Result<EdgeRules> {
+    Ok(serde_json::from_reader(File::open(path)?)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex_neighbours(row: usize, col: usize) -> Vec<(usize, (usize, usize))> {
+        Hex { rows: 6, cols: 6 }.neighbours(row, col)
+    }
+
+    #[test]
+    fn offset_and_axial_round_trip() {
+        for row in -5..=5 {
+            for col in -5..=5 {
+                let (q, r) = offset_to_axial(row, col);
+                assert_eq!(axial_to_offset(q, r), (row, col), "row {} col {}", row, col);
+            }
+        }
+        // Odd rows are shifted right, so their axial q starts one lower every two rows
+        assert_eq!(offset_to_axial(0, 0), (0, 0));
+        assert_eq!(offset_to_axial(1, 0), (0, 1));
+        assert_eq!(offset_to_axial(2, 0), (-1, 2));
+        assert_eq!(offset_to_axial(3, 2), (1, 3));
+    }
+
+    #[test]
+    fn hex_neighbours_of_even_row() {
+        // East, North-East, North-West, West, South-West, South-East
+        assert_eq!(hex_neighbours(2, 2), vec![(0, (2, 3)), (1, (1, 2)), (2, (1, 1)), (3, (2, 1)), (4, (3, 1)), (5, (3, 2))]);
+    }
+
+    #[test]
+    fn hex_neighbours_of_odd_row() {
+        assert_eq!(hex_neighbours(3, 2), vec![(0, (3, 3)), (1, (2, 3)), (2, (2, 2)), (3, (3, 1)), (4, (4, 2)), (5, (4, 3))]);
+    }
+
+    #[test]
+    fn hex_neighbours_stop_at_the_border() {
+        assert_eq!(hex_neighbours(0, 0), vec![(0, (0, 1)), (5, (1, 0))]);
+        assert_eq!(hex_neighbours(5, 5), vec![(2, (4, 5)), (3, (5, 4))]);
+    }
+
+    #[test]
+    fn hex_neighbours_point_back_across_the_opposite_edge() {
+        let hex = Hex { rows: 6, cols: 6 };
+        for row in 0..6 {
+            for col in 0..6 {
+                for (direction, (nrow, ncol)) in hex.neighbours(row, col) {
+                    assert!(hex.neighbours(nrow, ncol).contains(&(hex.opposite(direction), (row, col))),
+                        "({}, {}) to the {} of ({}, {})", nrow, ncol, hex.direction_name(direction), row, col);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,225 @@
+use std::collections::{BTreeMap, BTreeSet};
+use crate::tileset::Manifest;
+use crate::topology::{Topology, TopologyKind};
+
+// Ids each tile allows per direction, as `Grid::compatible` would get them:
+// per-side edge rules where a tile has them, its undirected rules on every side otherwise
+type Allowed = BTreeMap<usize, Vec<BTreeSet<usize>>>;
+
+// Checks a ruleset for mistakes that would only show up as odd maps or contradictions:
+// ids without a tile, one-sided rules, tiles with nothing allowed on a side, groups of
+// tiles that never touch each other and tiles that can never appear. Prints each problem
+// and returns how many were found.
+pub fn validate(manifest: &Manifest, kind: &TopologyKind) -> Result<usize, String> {
+    println!("Validating {} with {} tiles for {} grids", manifest.name, manifest.rules.len(), kind.build(1, 1)?.name());
+    let problems = problems(manifest, kind)?;
+    for problem in &problems {
+        println!("  {}", problem);
+    }
+    Ok(problems.len())
+}
+
+// Everything `validate` reports, one line per problem
+fn problems(manifest: &Manifest, kind: &TopologyKind) -> Result<BTreeSet<String>, String> {
+    let topology = kind.build(1, 1)?;
+    let tileset = manifest.tileset();
+    let label = |id: &usize| format!("{} {}", id, tileset.names.get(id).map_or("(unnamed)", String::as_str));
+
+    let mut problems = BTreeSet::new();
+    let ids: BTreeSet<usize> = manifest.rules.keys().copied().collect();
+    let mut mentioned: BTreeSet<usize> = ids.iter().chain(manifest.rules.values().flatten()).copied().collect();
+    mentioned.extend(manifest.tiles.keys());
+    mentioned.extend(manifest.weights.keys());
+    for (id, sides) in manifest.edge_rules.iter().flatten() {
+        mentioned.insert(*id);
+        mentioned.extend(sides.iter().flatten());
+    }
+    for id in &mentioned {
+        if !ids.contains(id) {
+            problems.insert(format!("Tile {} is mentioned but has no rules", label(id)));
+        }
+        if !tileset.names.contains_key(id) {
+            problems.insert(format!("Tile {} has no name in the tileset", id));
+        } else if !tileset.image_path(*id).is_file() {
+            problems.insert(format!("Tile {} has no image at {}", label(id), tileset.image_path(*id).display()));
+        }
+    }
+
+    let directions = topology.direction_count();
+    let mut directional = BTreeSet::new();
+    for (id, sides) in manifest.edge_rules.iter().flatten() {
+        if sides.len() == directions {
+            directional.insert(*id);
+        } else {
+            problems.insert(format!("Tile {} has {} edge rules, a {} grid needs {}", label(id), sides.len(), topology.name(), directions));
+        }
+    }
+    let allowed: Allowed = ids.iter()
+        .map(|&id| {
+            let sides = (0..directions)
+                .map(|direction| {
+                    let side = match manifest.edge_rules.as_ref().and_then(|edge_rules| edge_rules.get(&id)) {
+                        Some(sides) if directional.contains(&id) => &sides[direction],
+                        _ => &manifest.rules[&id],
+                    };
+                    side.iter().filter(|neighbour| ids.contains(neighbour)).copied().collect()
+                })
+                .collect();
+            (id, sides)
+        })
+        .collect();
+
+    for (id, sides) in &allowed {
+        for (direction, side) in sides.iter().enumerate() {
+            let opposite = topology.opposite(direction);
+            let one_way = directional.contains(id);
+            if side.is_empty() {
+                problems.insert(match one_way {
+                    true => format!("Tile {} allows nothing to the {}", label(id), topology.direction_name(direction)),
+                    false => format!("Tile {} allows no neighbours at all", label(id)),
+                });
+            }
+            for neighbour in side.iter().filter(|neighbour| !allowed[neighbour][opposite].contains(id)) {
+                problems.insert(match one_way || directional.contains(neighbour) {
+                    true => format!("Tile {} allows {} to the {}, but {} does not allow {} to the {}", label(id), label(neighbour),
+                        topology.direction_name(direction), label(neighbour), label(id), topology.direction_name(opposite)),
+                    false => format!("Tile {} allows {}, but {} does not allow {}", label(id), label(neighbour), label(neighbour), label(id)),
+                });
+            }
+        }
+    }
+
+    let groups = groups(&allowed);
+    if groups.len() > 1 {
+        let listed: Vec<String> = groups.iter().map(|group| format!("{:?}", group)).collect();
+        problems.insert(format!("Tiles fall apart into {} groups that never touch: {}", groups.len(), listed.join(", ")));
+    }
+
+    for (id, reason) in never_appearing(manifest, &allowed, topology.as_ref()) {
+        problems.insert(format!("Tile {} can never appear: {}", label(&id), reason));
+    }
+    Ok(problems)
+}
+
+// Tiles linked by a rule in either direction, each group in ascending order
+fn groups(allowed: &Allowed) -> Vec<Vec<usize>> {
+    let mut seen = BTreeSet::new();
+    let mut groups = vec![];
+    for &start in allowed.keys() {
+        if !seen.insert(start) {
+            continue;
+        }
+        let mut group = vec![];
+        let mut stack = vec![start];
+        while let Some(id) = stack.pop() {
+            group.push(id);
+            let linked = allowed[&id].iter().flatten()
+                .chain(allowed.iter().filter(|(_, sides)| sides.iter().any(|side| side.contains(&id))).map(|(other, _)| other));
+            for &other in linked {
+                if seen.insert(other) {
+                    stack.push(other);
+                }
+            }
+        }
+        group.sort_unstable();
+        groups.push(group);
+    }
+    groups
+}
+
+// Tiles that are never picked, or that need a neighbour on some side which itself can
+// never appear, repeated until no more drop out. Cells at the edge of a map have fewer
+// neighbours, so such a tile could at most show up there.
+fn never_appearing(manifest: &Manifest, allowed: &Allowed, topology: &dyn Topology) -> Vec<(usize, String)> {
+    let mut dropped = vec![];
+    let mut alive: BTreeSet<usize> = allowed.keys().copied().collect();
+    for (&id, &weight) in &manifest.weights {
+        if weight <= 0.0 && alive.remove(&id) {
+            dropped.push((id, format!("its weight is {}", weight)));
+        }
+    }
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for (&id, sides) in allowed {
+            if !alive.contains(&id) {
+                continue;
+            }
+            let unsupported = (0..sides.len()).find(|&direction| {
+                !sides[direction].iter().any(|neighbour| alive.contains(neighbour) && allowed[neighbour][topology.opposite(direction)].contains(&id))
+            });
+            if let Some(direction) = unsupported {
+                alive.remove(&id);
+                dropped.push((id, format!("no tile that can appear fits it to the {}", topology.direction_name(direction))));
+                changed = true;
+            }
+        }
+    }
+    dropped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use crate::topology::EdgeRules;
+
+    fn manifest(rules: &[(usize, Vec<usize>)], weights: &[(usize, f32)], edge_rules: Option<EdgeRules>) -> Manifest {
+        Manifest {
+            name: "test".to_string(),
+            tileset: PathBuf::from("test"),
+            tiles: rules.iter().map(|&(id, _)| (id, ["a", "b", "c", "d"][id - 1].to_string())).collect(),
+            rules: rules.iter().map(|(id, allowed)| (*id, allowed.clone())).collect(),
+            edge_rules,
+            weights: weights.iter().copied().collect(),
+            terrain: BTreeMap::new(),
+        }
+    }
+
+    // Problems apart from the missing images, which no test tileset has
+    fn rule_problems(manifest: &Manifest) -> Vec<String> {
+        problems(manifest, &TopologyKind::Square).unwrap().into_iter()
+            .filter(|problem| !problem.contains("has no image"))
+            .collect()
+    }
+
+    #[test]
+    fn sound_rules_have_no_problems() {
+        let manifest = manifest(&[(1, vec![1, 2]), (2, vec![1, 2])], &[], None);
+        assert!(rule_problems(&manifest).is_empty());
+    }
+
+    #[test]
+    fn one_sided_rule_is_reported() {
+        let manifest = manifest(&[(1, vec![1, 2]), (2, vec![2])], &[], None);
+        assert_eq!(rule_problems(&manifest), vec!["Tile 1 a allows 2 b, but 2 b does not allow 1 a"]);
+    }
+
+    #[test]
+    fn empty_sides_are_reported() {
+        let edge_rules: EdgeRules = [(1, vec![vec![], vec![1], vec![1], vec![1]])].into_iter().collect();
+        let manifest = manifest(&[(1, vec![1]), (2, vec![])], &[], Some(edge_rules));
+        let problems = rule_problems(&manifest);
+        assert!(problems.contains(&"Tile 1 a allows nothing to the north".to_string()), "{:?}", problems);
+        assert!(problems.contains(&"Tile 2 b allows no neighbours at all".to_string()), "{:?}", problems);
+    }
+
+    #[test]
+    fn disconnected_groups_are_reported() {
+        let manifest = manifest(&[(1, vec![1, 2]), (2, vec![1, 2]), (3, vec![3])], &[], None);
+        assert_eq!(rule_problems(&manifest), vec!["Tiles fall apart into 2 groups that never touch: [1, 2], [3]"]);
+    }
+
+    #[test]
+    fn unreachable_tiles_are_reported() {
+        // 4 is never picked, and 3 only fits next to 4
+        let supported = manifest(&[(1, vec![1, 3]), (3, vec![1, 4]), (4, vec![3, 4])], &[(4, 0.0)], None);
+        let problems = rule_problems(&supported);
+        assert!(problems.contains(&"Tile 4 d can never appear: its weight is 0".to_string()), "{:?}", problems);
+        assert!(!problems.iter().any(|problem| problem.starts_with("Tile 3 c can never appear")), "{:?}", problems);
+
+        let cut_off = manifest(&[(1, vec![1]), (3, vec![4]), (4, vec![3, 4])], &[(4, 0.0)], None);
+        let problems = rule_problems(&cut_off);
+        assert!(problems.contains(&"Tile 3 c can never appear: no tile that can appear fits it to the north".to_string()), "{:?}", problems);
+    }
+}
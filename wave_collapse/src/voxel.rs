@@ -0,0 +1,147 @@
+use serde::Serialize;
+use std::fs::{self, File};
+use std::io::{self, BufWriter};
+use std::path::Path;
+use crate::Grid;
+use crate::export::LegendEntry;
+use crate::render::TileImages;
+use crate::topology::TopologyKind;
+
+// MagicaVoxel limits
+const VOX_VERSION: i32 = 150;
+const VOX_MAX_SIZE: usize = 256;
+const VOX_MAX_COLOURS: usize = 255;
+
+// One collapsed cell of the block, z = 0 is the bottom layer
+#[derive(Serialize)]
+struct Block {
+    x: usize,
+    y: usize,
+    z: usize,
+    id: usize,
+}
+
+#[derive(Serialize)]
+struct BlockList {
+    width: usize,
+    depth: usize,
+    height: usize,
+    legend: Vec<LegendEntry>,
+    blocks: Vec<Block>,
+}
+
+// (width, depth, layers) of the grid; flat grids are a single layer
+fn dimensions(grid: &Grid) -> (usize, usize, usize) {
+    let width = grid.cells.first().map_or(0, |row| row.len());
+    match grid.topology.describe() {
+        TopologyKind::Voxel { rows } => (width, rows, grid.cells.len() / rows.max(1)),
+        _ => (width, grid.cells.len(), 1),
+    }
+}
+
+fn sorted_ids(grid: &Grid) -> Vec<usize> {
+    let mut ids: Vec<usize> = grid.rules.keys().copied().collect();
+    ids.sort_unstable();
+    ids
+}
+
+// Collapsed cells as blocks; column is x, row within the layer is y and the layer is z
+fn blocks(grid: &Grid) -> Vec<Block> {
+    let (_, depth, _) = dimensions(grid);
+    let mut blocks = vec![];
+    for (row, cells) in grid.cells.iter().enumerate() {
+        for (col, cell) in cells.iter().enumerate() {
+            if let Some(id) = cell.value {
+                blocks.push(Block { x: col, y: row % depth, z: row / depth, id });
+            }
+        }
+    }
+    blocks
+}
+
+// Average colour of each tile image, used as its voxel colour
+fn tile_colours(grid: &Grid, ids: &[usize]) -> io::Result<Vec<[u8; 4]>> {
+    let images = TileImages::load(grid).map_err(|e| io::Error::other(format!("{:?}", e)))?;
+    Ok(ids.iter()
+        .map(|&id| {
+            let mut sum = [0u64; 3];
+            let mut count = 0;
+            for pixel in images.get(id).into_iter().flat_map(|img| img.pixels()).filter(|pixel| pixel[3] > 0) {
+                for (channel, value) in sum.iter_mut().zip(pixel.0.iter()) {
+                    *channel += *value as u64;
+                }
+                count += 1;
+            }
+            match count {
+                0 => [128, 128, 128, 255],
+                _ => [(sum[0] / count) as u8, (sum[1] / count) as u8, (sum[2] / count) as u8, 255],
+            }
+        })
+        .collect())
+}
+
+fn write_chunk(out: &mut Vec<u8>, id: &[u8; 4], content: &[u8], children: &[u8]) {
+    out.extend_from_slice(id);
+    out.extend_from_slice(&(content.len() as i32).to_le_bytes());
+    out.extend_from_slice(&(children.len() as i32).to_le_bytes());
+    out.extend_from_slice(content);
+    out.extend_from_slice(children);
+}
+
+// Writes a MagicaVoxel model, one palette colour per tile
+pub fn export_vox(grid: &Grid, path: &Path) -> io::Result<()> {
+    let (width, depth, height) = dimensions(grid);
+    if width > VOX_MAX_SIZE || depth > VOX_MAX_SIZE || height > VOX_MAX_SIZE {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "MagicaVoxel models are limited to 256 voxels per side"));
+    }
+    let ids = sorted_ids(grid);
+    if ids.len() > VOX_MAX_COLOURS {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "MagicaVoxel palettes hold at most 255 tiles"));
+    }
+
+    let mut size = vec![];
+    for dimension in [width, depth, height] {
+        size.extend_from_slice(&(dimension as i32).to_le_bytes());
+    }
+    // Voxel colour indices start at 1, palette entry i is colour index i + 1
+    let blocks = blocks(grid);
+    let mut xyzi = (blocks.len() as i32).to_le_bytes().to_vec();
+    for block in &blocks {
+        let colour = ids.binary_search(&block.id).map_or(0, |index| index + 1);
+        xyzi.extend_from_slice(&[block.x as u8, block.y as u8, block.z as u8, colour as u8]);
+    }
+    let mut palette = vec![0u8; 256 * 4];
+    for (index, colour) in tile_colours(grid, &ids)?.into_iter().enumerate() {
+        palette[index * 4..index * 4 + 4].copy_from_slice(&colour);
+    }
+
+    let mut children = vec![];
+    write_chunk(&mut children, b"SIZE", &size, &[]);
+    write_chunk(&mut children, b"XYZI", &xyzi, &[]);
+    write_chunk(&mut children, b"RGBA", &palette, &[]);
+    let mut out = b"VOX ".to_vec();
+    out.extend_from_slice(&VOX_VERSION.to_le_bytes());
+    write_chunk(&mut out, b"MAIN", &[], &children);
+
+    fs::write(path, out)?;
+    println!("Exported {}x{}x{} MagicaVoxel model to {}", width, depth, height, path.display());
+    Ok(())
+}
+
+// Writes every collapsed cell as `{ "x", "y", "z", "id" }` together with the tile legend
+pub fn export_blocks_json(grid: &Grid, path: &Path) -> io::Result<()> {
+    let (width, depth, height) = dimensions(grid);
+    let list = BlockList {
+        width,
+        depth,
+        height,
+        legend: sorted_ids(grid).into_iter()
+            .map(|id| LegendEntry { id, name: grid.id_to_name(id as u32).to_string() })
+            .collect(),
+        blocks: blocks(grid),
+    };
+    let writer = BufWriter::new(File::create(path)?);
+    serde_json::to_writer_pretty(writer, &list)?;
+    println!("Exported {} blocks to {}", list.blocks.len(), path.display());
+    Ok(())
+}